@@ -0,0 +1,169 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8PathBuf;
+use cargo_metadata::{Message, MetadataCommand};
+use log::*;
+
+#[derive(Debug, Default)]
+pub struct AuditOptions {
+    /// Path to the root manifest of the workspace
+    pub manifest_path: Option<Utf8PathBuf>,
+
+    /// Read `cargo build --message-format=json` output from this
+    /// file instead of running the build. `-` reads from stdin.
+    pub input: Option<Utf8PathBuf>,
+
+    /// Additional cargo build args
+    pub args: Vec<String>,
+}
+
+/// The result of auditing a build.
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    /// Dependencies whose artifacts were reused
+    pub fresh: Vec<String>,
+
+    /// Dependencies that were compiled during the build
+    pub rebuilt: Vec<String>,
+}
+
+/// Audit a build for dependencies that were recompiled.
+///
+/// Every `compiler-artifact` message for a package outside of the
+/// workspace is checked. If any artifact of a package was not fresh,
+/// the package was recompiled instead of being reused from the
+/// skeleton build.
+pub fn audit_build(opts: AuditOptions) -> Result<AuditReport> {
+    let mut metadata = MetadataCommand::new();
+    if let Some(path) = &opts.manifest_path {
+        metadata.manifest_path(path);
+    }
+    let metadata = metadata
+        .no_deps()
+        .exec()
+        .context("executing cargo metadata")?;
+
+    let members: HashSet<&str> = metadata
+        .workspace_members
+        .iter()
+        .map(|id| id.repr.as_str())
+        .collect();
+
+    // Package ID to whether every artifact was fresh
+    let mut packages: BTreeMap<String, bool> = BTreeMap::new();
+
+    let mut record = |reader: &mut dyn BufRead| -> Result<()> {
+        for message in Message::parse_stream(reader) {
+            let artifact = match message.context("reading cargo message")? {
+                Message::CompilerArtifact(artifact) => artifact,
+                _ => continue,
+            };
+
+            if members.contains(artifact.package_id.repr.as_str()) {
+                continue;
+            }
+
+            let name = package_display_name(&artifact.package_id.repr);
+            let fresh = packages.entry(name).or_insert(true);
+            *fresh &= artifact.fresh;
+        }
+
+        Ok(())
+    };
+
+    match opts.input.as_ref().map(|path| path.as_str()) {
+        Some("-") => record(&mut io::stdin().lock())?,
+        Some(path) => {
+            let file = File::open(path).context("opening cargo build output")?;
+            record(&mut BufReader::new(file))?;
+        }
+        None => {
+            let cargo = std::env::var("CARGO").unwrap_or("cargo".into());
+
+            let mut cmd = Command::new(&cargo);
+            cmd.args(["build", "--message-format=json"])
+                .args(&opts.args);
+            if let Some(path) = &opts.manifest_path {
+                cmd.arg("--manifest-path").arg(path);
+            }
+
+            debug!(
+                "Running `cargo build --message-format=json {}`",
+                opts.args.join(" ")
+            );
+
+            let mut child = cmd
+                .stdout(Stdio::piped())
+                .spawn()
+                .context("executing `cargo build` command")?;
+
+            let stdout = child.stdout.take().expect("stdout is piped");
+            record(&mut BufReader::new(stdout))?;
+
+            let ecode = child.wait().context("waiting on cargo build process")?;
+
+            if !ecode.success() {
+                bail!("Failed to execute cargo build")
+            }
+        }
+    }
+
+    let mut report = AuditReport::default();
+
+    for (name, fresh) in packages {
+        if fresh {
+            report.fresh.push(name);
+        } else {
+            report.rebuilt.push(name);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Format a package ID as `name vX.Y.Z`.
+///
+/// Cargo uses `source#name@version` IDs since 1.77, and
+/// `name version (source)` IDs before that.
+fn package_display_name(id: &str) -> String {
+    let (name, version) = match id.rsplit_once('#') {
+        Some((_, spec)) => spec.split_once('@').unwrap_or((spec, "")),
+        None => {
+            let mut parts = id.split(' ');
+            (parts.next().unwrap_or(id), parts.next().unwrap_or(""))
+        }
+    };
+
+    if version.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} v{}", name, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_package_ids() {
+        assert_eq!(
+            "log v0.4.22",
+            package_display_name(
+                "registry+https://github.com/rust-lang/crates.io-index#log@0.4.22"
+            )
+        );
+        assert_eq!(
+            "log v0.4.22",
+            package_display_name(
+                "log 0.4.22 (registry+https://github.com/rust-lang/crates.io-index)"
+            )
+        );
+    }
+}
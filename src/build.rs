@@ -1,12 +1,28 @@
-use anyhow::{bail, Context, Result};
-use camino::Utf8PathBuf;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use itertools::Itertools;
 use log::*;
-use std::{collections::HashSet, env, process::Command};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    process::Command,
+    time::{Duration, Instant},
+};
 
+use crate::clean::target_dir;
+use crate::error::SkeletonError;
 use crate::package::PackageId;
-use crate::workspace::Workspace;
+use crate::stamp::{target_dir_arg, toolchain_version, BuildStamp, StampEntry, StampInputs};
+use crate::unpack::{unpack_skeleton_archive, UnpackOptions};
+use crate::workspace::{find_root, Workspace};
 
+/// Options for planning and running a skeleton build.
+///
+/// Fields may be added in future releases, so the options are created
+/// with [`BuildOptions::new`] or `Default` and then changed.
 #[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct BuildOptions {
     /// Path to the root manifest of the workspace
     pub manifest_path: Option<Utf8PathBuf>,
@@ -20,23 +36,210 @@ pub struct BuildOptions {
     /// Build all packages in the workspace
     pub all: bool,
 
+    /// The cargo command to compile the dependencies with
+    pub cargo_command: CargoCommand,
+
+    /// Program to run instead of cargo, e.g. `cross`
+    pub wrapper: Option<String>,
+
+    /// Subcommand words to use instead of the cargo command, e.g.
+    /// `zigbuild` or `auditable build`
+    pub subcommand: Vec<String>,
+
+    /// Profiles to build the dependencies for
+    pub profiles: Vec<String>,
+
+    /// Target triples to build the dependencies for
+    pub targets: Vec<String>,
+
+    /// Build even if the build stamp is up to date
+    pub force: bool,
+
     /// Additional cargo build args
     pub args: Vec<String>,
+
+    /// Cargo args that only change cargo's output, e.g. `-v` or
+    /// `--color never`. They are not part of the build stamp.
+    pub output_args: Vec<String>,
 }
 
-/// Build a skeleton package by compiling all of it's dependencies.
-pub fn build_skeleton_package(opts: BuildOptions) -> Result<()> {
-    let workspace_root: Utf8PathBuf = opts
-        .manifest_path
-        .and_then(|p| p.parent().map(|p| p.to_owned()))
-        .unwrap_or_else(|| {
-            env::current_dir()
-                .expect("getting current dir")
-                .try_into()
-                .expect("current path should be utf-8")
-        });
+impl BuildOptions {
+    /// Create options building every package of the skeleton with the
+    /// given root manifest.
+    pub fn new(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            manifest_path: Some(manifest_path.into()),
+            ..Default::default()
+        }
+    }
+}
+
+/// The cargo command used to compile dependencies.
+///
+/// Each command produces different artifacts, so the dependencies
+/// should be compiled with the same command as the final build.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum CargoCommand {
+    /// `cargo build`
+    #[default]
+    Build,
+    /// `cargo check`
+    Check,
+    /// `cargo clippy`
+    Clippy,
+    /// `cargo doc`
+    Doc,
+    /// `cargo build`, with `--tests` for members with kept source
+    ///
+    /// `Skeleton.lock` only records normal dependencies, so
+    /// dev-dependencies are not prebuilt and the final `cargo test`
+    /// still compiles them, along with any dependency whose features
+    /// they unify differently.
+    Test,
+}
+
+impl CargoCommand {
+    /// Returns the cargo arguments for the command.
+    pub fn args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Build | Self::Test => &["build"],
+            Self::Check => &["check"],
+            Self::Clippy => &["clippy"],
+            Self::Doc => &["doc"],
+        }
+    }
+}
+
+/// The cargo commands a skeleton build runs, in order.
+#[derive(Debug, Serialize)]
+#[non_exhaustive]
+pub struct BuildPlan {
+    /// Directory the commands are run in
+    pub workspace_root: Utf8PathBuf,
+
+    /// A command for each package with dependencies to build
+    pub commands: Vec<BuildCommand>,
+}
+
+/// A profile and target combination the dependencies are built for.
+/// `None` uses cargo's default.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildVariant {
+    /// The profile to build with
+    pub profile: Option<String>,
+
+    /// The target triple to build for
+    pub target: Option<String>,
+}
+
+impl Display for BuildVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let profile = self.profile.as_deref().unwrap_or("default");
+        let target = self.target.as_deref().unwrap_or("host");
+
+        write!(f, "profile {}, target {}", profile, target)
+    }
+}
+
+/// The result of building the dependencies for each variant.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct BuildReport {
+    /// Variants whose dependencies were already built with the same
+    /// inputs, according to the build stamp
+    pub fresh: Vec<BuildVariant>,
+
+    /// Variants whose dependencies were built
+    pub built: Vec<BuildVariant>,
+
+    /// Variants whose build failed, with the error
+    pub failed: Vec<(BuildVariant, SkeletonError)>,
+
+    /// The cargo commands that were run
+    pub commands: Vec<CommandReport>,
+
+    /// How long unpacking the skeleton archive took, for builds
+    /// started with [`build_skeleton_archive`]
+    pub unpack_duration: Option<Duration>,
+}
+
+/// A cargo command that was run by a build.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CommandReport {
+    /// Name of the package the dependencies were built for
+    pub package: String,
+
+    /// The profile and target the command built for
+    pub variant: BuildVariant,
+
+    /// The command line, formatted for a shell
+    pub command: String,
+
+    /// How long the command took
+    pub duration: Duration,
+
+    /// Whether the command succeeded
+    pub success: bool,
+}
 
-    let mut workspace = Workspace::new(workspace_root);
+/// A cargo command building the dependencies of a package.
+#[derive(Debug, Serialize)]
+#[non_exhaustive]
+pub struct BuildCommand {
+    /// Name of the package the dependencies are built for
+    pub package: String,
+
+    /// The profile and target the command builds for
+    #[serde(flatten)]
+    pub variant: BuildVariant,
+
+    /// The cargo binary or wrapper to run
+    pub program: String,
+
+    /// The arguments passed to cargo
+    pub args: Vec<String>,
+
+    /// The arguments that only change cargo's output
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output_args: Vec<String>,
+}
+
+impl BuildCommand {
+    /// Format the command for a POSIX shell.
+    pub fn to_shell(&self) -> String {
+        Some(&self.program)
+            .into_iter()
+            .chain(self.args.iter())
+            .chain(self.output_args.iter())
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl BuildPlan {
+    /// Format the plan as a POSIX shell script.
+    pub fn to_shell(&self) -> String {
+        let mut out = format!("cd {}\n", shell_quote(self.workspace_root.as_str()));
+
+        for command in self.commands.iter() {
+            out.push_str(&command.to_shell());
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+/// Work out the cargo commands needed to build a skeleton package's
+/// dependencies, without running them.
+pub fn plan_skeleton_build(opts: &BuildOptions) -> Result<BuildPlan, SkeletonError> {
+    let workspace_root = find_root(opts.manifest_path.as_deref())?;
+
+    let mut workspace = Workspace::new(workspace_root.clone());
 
     workspace.load_lockfile()?;
 
@@ -62,40 +265,516 @@ pub fn build_skeleton_package(opts: BuildOptions) -> Result<()> {
         .collect::<HashSet<&PackageId>>();
 
     if build_ids.is_empty() {
-        bail!("No packages to build");
+        return Err(anyhow!("No packages to build").into());
     }
 
-    let cargo = std::env::var("CARGO").unwrap_or("cargo".into());
+    let program = match &opts.wrapper {
+        Some(wrapper) => wrapper.clone(),
+        None => std::env::var("CARGO").unwrap_or("cargo".into()),
+    };
+
+    let subcommand: Vec<String> = if opts.subcommand.is_empty() {
+        opts.cargo_command.args().iter().map(|arg| arg.to_string()).collect()
+    } else {
+        opts.subcommand.clone()
+    };
+
+    // `--tests` would also build the tests of every dependency, so
+    // only the tests of members with kept source are built.
+    let build_tests = opts.subcommand.is_empty() && opts.cargo_command == CargoCommand::Test;
+
+    let mut build_ids: Vec<_> = build_ids.into_iter().collect();
+    build_ids.sort();
+
+    let profiles: Vec<Option<&String>> = if opts.profiles.is_empty() {
+        vec![None]
+    } else {
+        opts.profiles.iter().map(Some).collect()
+    };
+    let targets: Vec<Option<&String>> = if opts.targets.is_empty() {
+        vec![None]
+    } else {
+        opts.targets.iter().map(Some).collect()
+    };
+
+    let variants = profiles
+        .iter()
+        .cartesian_product(targets.iter())
+        .map(|(profile, target)| BuildVariant {
+            profile: profile.cloned(),
+            target: target.cloned(),
+        });
+
+    let mut commands = vec![];
 
-    for pkg_id in build_ids.iter() {
+    for (variant, pkg_id) in variants.cartesian_product(build_ids) {
         let pkg = workspace
             .get_package(pkg_id)
             .expect("present if ID was found");
 
-        info!("Building package dependencies: {}", pkg.name);
-
-        let pkg_args: Vec<&str> = pkg
+        // Members with kept source are compiled like dependencies.
+        let deps: Vec<&PackageId> = pkg
             .dependencies
             .iter()
-            .filter(|id| !workspace.is_member(id))
-            .flat_map(|id| vec!["-p", id.as_str()])
+            .filter(|id| !workspace.is_member(id) || workspace[id].keep_source)
+            .chain(Some(&pkg.id).filter(|_| pkg.keep_source && !build_tests))
+            .collect();
+        let tests: Vec<&PackageId> = Some(&pkg.id)
+            .filter(|_| pkg.keep_source && build_tests)
+            .into_iter()
+            .collect();
+
+        // Without any `-p` flags cargo would build the stubs.
+        if deps.is_empty() && tests.is_empty() {
+            debug!("No dependencies to build for {}", pkg.name);
+            continue;
+        }
+
+        let variant_args: Vec<String> = variant
+            .profile
+            .iter()
+            .flat_map(|profile| ["--profile".to_string(), profile.clone()])
+            .chain(
+                variant
+                    .target
+                    .iter()
+                    .flat_map(|target| ["--target".to_string(), target.clone()]),
+            )
             .collect();
 
-        debug!("Running `cargo build {} {}`", pkg_args.join(" "), &opts.args.join(" "));
+        for (target_args, ids) in [(None, deps), (Some("--tests"), tests)] {
+            if ids.is_empty() {
+                continue;
+            }
+
+            commands.push(BuildCommand {
+                package: pkg.name.clone(),
+                program: program.clone(),
+                args: subcommand
+                    .iter()
+                    .cloned()
+                    .chain(target_args.map(String::from))
+                    .chain(ids.iter().flat_map(|id| ["-p".to_string(), id.to_string()]))
+                    .chain(variant_args.iter().cloned())
+                    .chain(opts.args.iter().cloned())
+                    .collect(),
+                output_args: opts.output_args.clone(),
+                variant: variant.clone(),
+            });
+        }
+    }
+
+    Ok(BuildPlan {
+        workspace_root,
+        commands,
+    })
+}
+
+/// Build a skeleton package by compiling all of it's dependencies.
+pub fn build_skeleton_package(opts: BuildOptions) -> Result<(), SkeletonError> {
+    let report = build_skeleton_variants(opts)?;
+
+    match report.failed.into_iter().next() {
+        Some((variant, SkeletonError::Other(err))) => Err(SkeletonError::Other(
+            err.context(format!("building {}", variant)),
+        )),
+        Some((_, err)) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Build a skeleton package's dependencies for every profile and
+/// target combination.
+///
+/// A failed build does not stop the other combinations from being
+/// built. Each combination is reported as fresh, built or failed.
+///
+/// Finished builds are recorded in a stamp in the target directory.
+/// A combination is skipped if the stamp records a build with the
+/// same `Skeleton.lock`, manifests, Cargo config, kept member sources,
+/// build environment variables, commands, and toolchain, unless
+/// `force` is set.
+pub fn build_skeleton_variants(opts: BuildOptions) -> Result<BuildReport, SkeletonError> {
+    let plan = plan_skeleton_build(&opts)?;
+    let root = &plan.workspace_root;
+
+    let target_dir = target_dir(root, target_dir_arg(&opts.args).map(|dir| root.join(dir)));
+    let mut stamp = BuildStamp::load(&target_dir);
+
+    let inputs = StampInputs::load(root, &target_dir).context("reading build inputs")?;
+    let toolchain = toolchain_version(root)
+        .inspect_err(|err| warn!("Not using build stamp: {:#}", err))
+        .ok();
+
+    let mut report = BuildReport::default();
+
+    for (variant, commands) in plan.commands.iter().chunk_by(|cmd| &cmd.variant).into_iter() {
+        let commands: Vec<_> = commands.collect();
+
+        let entry = toolchain.as_ref().map(|toolchain| {
+            StampEntry::new(&inputs, toolchain, variant, commands.iter().copied())
+        });
+
+        if let Some(entry) = &entry {
+            if !opts.force && stamp.contains(&entry.digest) {
+                info!("Dependencies for {} are up to date", variant);
+                report.fresh.push(variant.clone());
+                continue;
+            }
+        }
+
+        match run_commands(root, commands.into_iter(), &mut report.commands) {
+            Ok(()) => {
+                report.built.push(variant.clone());
+
+                if let Some(entry) = entry {
+                    stamp.record(entry);
+                    stamp.save(&target_dir)?;
+                }
+            }
+            Err(err) => {
+                info!("Failed to build {}: {:#}", variant, err);
+                report.failed.push((variant.clone(), err.into()));
+            }
+        }
+    }
 
-        let mut child = Command::new(&cargo)
-            .arg("build")
-            .args(pkg_args)
-            .args(&opts.args)
+    Ok(report)
+}
+
+/// Run cargo commands in order, stopping at the first failure. Each
+/// command that ran is added to the reports.
+fn run_commands<'a>(
+    workspace_root: &Utf8Path,
+    commands: impl Iterator<Item = &'a BuildCommand>,
+    reports: &mut Vec<CommandReport>,
+) -> Result<()> {
+    for command in commands {
+        info!("Building package dependencies: {}", command.package);
+
+        debug!("Running `{}`", command.to_shell());
+
+        let start = Instant::now();
+
+        // Cargo reads its config starting from the current directory,
+        // so run it in the workspace root even when the workspace was
+        // given with a manifest path, e.g. an unpack destination.
+        let mut child = Command::new(&command.program)
+            .current_dir(workspace_root)
+            .args(&command.args)
+            .args(&command.output_args)
             .spawn()
-            .context("executing `cargo build` command")?;
+            .context(format!("executing `{}` command", command.to_shell()))?;
 
-        let ecode = child.wait().context("waiting on cargo build process")?;
+        let ecode = child.wait().context("waiting on cargo process")?;
+
+        reports.push(CommandReport {
+            package: command.package.clone(),
+            variant: command.variant.clone(),
+            command: command.to_shell(),
+            duration: start.elapsed(),
+            success: ecode.success(),
+        });
 
         if !ecode.success() {
-            bail!("Failed to execute cargo build")
+            return Err(SkeletonError::CommandFailed {
+                command: command.to_shell(),
+                status: ecode,
+            }
+            .into());
         }
     }
 
     Ok(())
 }
+
+/// Unpack a skeleton archive and build its dependencies for every
+/// profile and target combination, like [`build_skeleton_variants`].
+///
+/// The archive is unpacked with the same checks as
+/// [`unpack_skeleton_archive`], and the build only starts once the
+/// whole archive was unpacked. Unless a manifest path is given, the
+/// skeleton is built in the unpack destination.
+pub fn build_skeleton_archive(
+    unpack: UnpackOptions,
+    mut build: BuildOptions,
+) -> Result<BuildReport, SkeletonError> {
+    let dest_path = unpack.dest_path.clone();
+    let start = Instant::now();
+
+    unpack_skeleton_archive(unpack).context("unpacking skeleton archive")?;
+
+    let unpack_duration = start.elapsed();
+
+    if build.manifest_path.is_none() {
+        build.manifest_path = dest_path.map(|path| path.join("Cargo.toml"));
+    }
+
+    let mut report = build_skeleton_variants(build)?;
+    report.unpack_duration = Some(unpack_duration);
+
+    Ok(report)
+}
+
+/// Quote an argument for a POSIX shell, if needed.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@+,%#".contains(c);
+
+    // A leading `#` would start a comment.
+    if !arg.is_empty() && !arg.starts_with('#') && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::SkeletonError, lockfile::LOCKFILE_NAME, SkeletonBuilder};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    const PLAN_LOCKFILE: &str = r#"
+[[package]]
+name = "app"
+id = "app"
+dependencies = ["lib-a", "proto", "serde@1.0.0"]
+
+[[package]]
+name = "lib-a"
+id = "lib-a"
+dependencies = ["log@0.4.0"]
+
+[[package]]
+name = "proto"
+id = "proto"
+dependencies = ["prost@0.12.0"]
+keep-source = true
+
+[[package]]
+name = "tool"
+id = "tool"
+dependencies = []
+"#;
+
+    fn plan_commands(root: &Utf8Path, opts: BuildOptions) -> Vec<String> {
+        let opts = BuildOptions {
+            manifest_path: Some(root.join("Cargo.toml")),
+            ..opts
+        };
+
+        plan_skeleton_build(&opts)
+            .unwrap()
+            .commands
+            .into_iter()
+            .map(|command| format!("{}: {}", command.variant, command.args.join(" ")))
+            .collect()
+    }
+
+    #[test]
+    fn plans_skeleton_builds() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", PLAN_LOCKFILE);
+
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+
+        let cases: Vec<(&str, BuildOptions, Vec<&str>)> = vec![
+            (
+                "all packages, kept source passed with -p, empty skipped",
+                BuildOptions::default(),
+                vec![
+                    "profile default, target host: build -p proto -p serde@1.0.0",
+                    "profile default, target host: build -p log@0.4.0",
+                    "profile default, target host: build -p prost@0.12.0 -p proto",
+                ],
+            ),
+            (
+                "selected package",
+                BuildOptions {
+                    packages: strings(&["lib-a"]),
+                    ..Default::default()
+                },
+                vec!["profile default, target host: build -p log@0.4.0"],
+            ),
+            (
+                "member dependencies of selected packages",
+                BuildOptions {
+                    packages: strings(&["app"]),
+                    exclude: strings(&["tool"]),
+                    ..Default::default()
+                },
+                vec![
+                    "profile default, target host: build -p proto -p serde@1.0.0",
+                    "profile default, target host: build -p log@0.4.0",
+                    "profile default, target host: build -p prost@0.12.0 -p proto",
+                ],
+            ),
+            (
+                "excluded package",
+                BuildOptions {
+                    exclude: strings(&["app"]),
+                    ..Default::default()
+                },
+                vec![
+                    "profile default, target host: build -p log@0.4.0",
+                    "profile default, target host: build -p prost@0.12.0 -p proto",
+                ],
+            ),
+            (
+                "every profile and target combination",
+                BuildOptions {
+                    packages: strings(&["lib-a"]),
+                    profiles: strings(&["dev", "release"]),
+                    targets: strings(&["x86_64-unknown-linux-musl", "aarch64-unknown-linux-musl"]),
+                    ..Default::default()
+                },
+                vec![
+                    "profile dev, target x86_64-unknown-linux-musl: build -p log@0.4.0 --profile dev --target x86_64-unknown-linux-musl",
+                    "profile dev, target aarch64-unknown-linux-musl: build -p log@0.4.0 --profile dev --target aarch64-unknown-linux-musl",
+                    "profile release, target x86_64-unknown-linux-musl: build -p log@0.4.0 --profile release --target x86_64-unknown-linux-musl",
+                    "profile release, target aarch64-unknown-linux-musl: build -p log@0.4.0 --profile release --target aarch64-unknown-linux-musl",
+                ],
+            ),
+            (
+                "cargo command and args",
+                BuildOptions {
+                    packages: strings(&["lib-a"]),
+                    cargo_command: CargoCommand::Check,
+                    args: strings(&["--locked"]),
+                    ..Default::default()
+                },
+                vec!["profile default, target host: check -p log@0.4.0 --locked"],
+            ),
+            (
+                "subcommand replaces cargo command",
+                BuildOptions {
+                    packages: strings(&["lib-a"]),
+                    cargo_command: CargoCommand::Clippy,
+                    subcommand: strings(&["auditable", "build"]),
+                    ..Default::default()
+                },
+                vec!["profile default, target host: auditable build -p log@0.4.0"],
+            ),
+            (
+                "tests only built for kept source",
+                BuildOptions {
+                    exclude: strings(&["app"]),
+                    cargo_command: CargoCommand::Test,
+                    ..Default::default()
+                },
+                vec![
+                    "profile default, target host: build -p log@0.4.0",
+                    "profile default, target host: build -p prost@0.12.0",
+                    "profile default, target host: build --tests -p proto",
+                ],
+            ),
+        ];
+
+        for (name, opts, expected) in cases {
+            assert_eq!(plan_commands(root, opts), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn plans_wrapper_program() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", PLAN_LOCKFILE);
+
+        let opts = BuildOptions {
+            manifest_path: Some(root.join("Cargo.toml")),
+            packages: vec!["lib-a".into()],
+            wrapper: Some("cross".into()),
+            output_args: vec!["-v".into()],
+            ..Default::default()
+        };
+        let plan = plan_skeleton_build(&opts).unwrap();
+
+        assert_eq!(plan.workspace_root, root);
+        assert_eq!(plan.commands.len(), 1);
+        assert_eq!(plan.commands[0].package, "lib-a");
+        assert_eq!(plan.commands[0].program, "cross");
+        assert_eq!(plan.commands[0].output_args, ["-v"]);
+    }
+
+    #[test]
+    fn fails_without_packages() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", PLAN_LOCKFILE);
+
+        let opts = BuildOptions {
+            manifest_path: Some(root.join("Cargo.toml")),
+            packages: vec!["lib-b".into()],
+            ..Default::default()
+        };
+        let err = plan_skeleton_build(&opts).unwrap_err();
+
+        assert!(matches!(
+            err,
+            SkeletonError::PackageNotFound { spec } if spec == "lib-b"
+        ));
+    }
+
+    #[test]
+    fn builds_from_archive() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let tmp_root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+        let root = tmp_root.join("app");
+        let archive_path = tmp_root.join("skeleton.tar");
+
+        write(&root, "Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\n");
+        write(&root, "src/main.rs", "fn main() {}");
+
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .current_dir(&root)
+            .other_options(vec!["--offline".to_string()])
+            .exec()
+            .unwrap();
+        SkeletonBuilder::new(metadata).write_to_path(&archive_path).unwrap();
+
+        let unpack = || UnpackOptions {
+            archive_path: Some(archive_path.clone()),
+            dest_path: Some(root.clone()),
+        };
+
+        let err = build_skeleton_archive(unpack(), BuildOptions::default()).unwrap_err();
+
+        assert!(matches!(err, SkeletonError::ExistingWorkspace { .. }));
+
+        // The skeleton is unpacked at the path of the workspace.
+        fs::rename(&root, tmp_root.join("app-real")).unwrap();
+        fs::create_dir(&root).unwrap();
+
+        let report = build_skeleton_archive(unpack(), BuildOptions::default()).unwrap();
+
+        assert!(root.join(LOCKFILE_NAME).is_file());
+        assert!(report.unpack_duration.is_some());
+        assert!(report.commands.is_empty());
+        assert!(report.failed.is_empty());
+    }
+
+    #[test]
+    fn quotes_shell_args() {
+        assert_eq!("--release", shell_quote("--release"));
+        assert_eq!(
+            "registry+https://github.com/rust-lang/crates.io-index#log@0.4.22",
+            shell_quote("registry+https://github.com/rust-lang/crates.io-index#log@0.4.22")
+        );
+        assert_eq!("'--features=a b'", shell_quote("--features=a b"));
+        assert_eq!(r"'it'\''s'", shell_quote("it's"));
+        assert_eq!("''", shell_quote(""));
+        assert_eq!("'#x'", shell_quote("#x"));
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+/// The standard file name used for Cargo's lockfile on disk.
+pub const CARGO_LOCK_NAME: &str = "Cargo.lock";
+
+/// A Cargo lockfile that can be pruned to a subset of its packages.
+///
+/// Cargo compares the lockfile it would write with the file on disk
+/// when `--locked` is passed, so the file is written back in the
+/// exact format Cargo uses. Anything other than `[[package]]` entries
+/// is preserved as-is.
+#[derive(Debug)]
+pub struct CargoLock {
+    /// Everything before the first package, e.g. the header comment
+    /// and `version` key
+    header: String,
+
+    /// The locked packages, in the original order
+    packages: Vec<LockedPackage>,
+
+    /// Everything after the last package, e.g. `[[patch.unused]]`
+    trailer: String,
+
+    /// Whether the lockfile uses the original format, which stores
+    /// checksums in a `[metadata]` table
+    is_v1: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    replace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackageEntry {
+    package: Vec<LockedPackage>,
+}
+
+impl CargoLock {
+    /// Parse a Cargo lockfile.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut header = String::new();
+        let mut packages = vec![];
+        let mut trailer = String::new();
+
+        let mut sections = split_sections(s).into_iter().peekable();
+
+        while let Some(section) = sections.next_if(|section| !is_package(section)) {
+            header.push_str(section);
+        }
+
+        while let Some(section) = sections.next_if(|section| is_package(section)) {
+            let entry: PackageEntry =
+                toml::from_str(section).context("parsing Cargo.lock package")?;
+            packages.extend(entry.package);
+        }
+
+        for section in sections {
+            if is_package(section) {
+                bail!("Cargo.lock packages are not contiguous");
+            }
+            trailer.push_str(section);
+        }
+
+        let is_v1 = split_sections(&trailer)
+            .into_iter()
+            .any(|section| section.starts_with("[metadata]"));
+
+        Ok(Self {
+            header,
+            packages,
+            trailer,
+            is_v1,
+        })
+    }
+
+    /// Remove every package that is not reachable from the given
+    /// local packages, identified by name and version.
+    pub fn prune<'a>(&mut self, roots: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<()> {
+        let mut stack: Vec<usize> = roots
+            .into_iter()
+            .map(|(name, version)| {
+                self.packages
+                    .iter()
+                    .position(|pkg| {
+                        pkg.source.is_none() && pkg.name == name && pkg.version == version
+                    })
+                    .ok_or_else(|| anyhow!("Cargo.lock is missing package {} {}", name, version))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut reachable = BTreeSet::new();
+
+        while let Some(idx) = stack.pop() {
+            if !reachable.insert(idx) {
+                continue;
+            }
+
+            let pkg = &self.packages[idx];
+
+            for dep in pkg.dependencies.iter().chain(pkg.replace.iter()) {
+                stack.push(self.resolve(dep)?);
+            }
+        }
+
+        // Dependencies are resolved before removing packages, since
+        // removing packages may change how they are encoded.
+        let deps: HashMap<usize, Vec<usize>> = reachable
+            .iter()
+            .map(|idx| -> Result<(usize, Vec<usize>)> {
+                let deps = self.packages[*idx]
+                    .dependencies
+                    .iter()
+                    .map(|dep| self.resolve(dep))
+                    .collect::<Result<_>>()?;
+                Ok((*idx, deps))
+            })
+            .collect::<Result<_>>()?;
+
+        let kept: Vec<usize> = reachable.into_iter().collect();
+        let new_idx: HashMap<usize, usize> = kept
+            .iter()
+            .enumerate()
+            .map(|(new, old)| (*old, new))
+            .collect();
+
+        let mut packages: Vec<Option<LockedPackage>> = std::mem::take(&mut self.packages)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        self.packages = kept
+            .iter()
+            .map(|idx| packages[*idx].take().expect("packages are kept once"))
+            .collect();
+
+        // Version 1 lockfiles always encode the full package ID.
+        if !self.is_v1 {
+            for (new, old) in kept.iter().enumerate() {
+                self.packages[new].dependencies = deps[old]
+                    .iter()
+                    .map(|dep| self.encode_dependency(new_idx[dep]))
+                    .collect();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode a dependency the way Cargo does, adding the version and
+    /// source only when they are needed to tell packages apart.
+    fn encode_dependency(&self, idx: usize) -> String {
+        let pkg = &self.packages[idx];
+
+        let same_name: Vec<&LockedPackage> = self
+            .packages
+            .iter()
+            .filter(|other| other.name == pkg.name)
+            .collect();
+
+        if same_name.len() == 1 {
+            return pkg.name.clone();
+        }
+
+        let same_version = same_name
+            .iter()
+            .filter(|other| other.version == pkg.version)
+            .count();
+
+        match &pkg.source {
+            Some(source) if same_version > 1 => {
+                format!("{} {} ({})", pkg.name, pkg.version, source)
+            }
+            _ => format!("{} {}", pkg.name, pkg.version),
+        }
+    }
+
+    /// Find the index of the package a dependency string refers to.
+    fn resolve(&self, dep: &str) -> Result<usize> {
+        let (name, rest) = dep.split_once(' ').unwrap_or((dep, ""));
+        let (version, source) = match rest.split_once(' ') {
+            Some((version, source)) => (Some(version), Some(source)),
+            None if rest.is_empty() => (None, None),
+            None => (Some(rest), None),
+        };
+        let source = source.map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+
+        self.packages
+            .iter()
+            .position(|pkg| {
+                pkg.name == name
+                    && version.iter().all(|&version| pkg.version == version)
+                    && source
+                        .iter()
+                        .all(|&source| pkg.source.as_deref() == Some(source))
+            })
+            .ok_or_else(|| anyhow!("Cargo.lock dependency {} did not match any package", dep))
+    }
+}
+
+impl std::fmt::Display for CargoLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.header)?;
+
+        for (idx, pkg) in self.packages.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+
+            writeln!(f, "[[package]]")?;
+            writeln!(f, "name = {}", quote(&pkg.name))?;
+            writeln!(f, "version = {}", quote(&pkg.version))?;
+            if let Some(source) = &pkg.source {
+                writeln!(f, "source = {}", quote(source))?;
+            }
+            if let Some(checksum) = &pkg.checksum {
+                writeln!(f, "checksum = {}", quote(checksum))?;
+            }
+            if !pkg.dependencies.is_empty() {
+                writeln!(f, "dependencies = [")?;
+                for dep in pkg.dependencies.iter() {
+                    writeln!(f, " {},", quote(dep))?;
+                }
+                writeln!(f, "]")?;
+            }
+            if let Some(replace) = &pkg.replace {
+                writeln!(f, "replace = {}", quote(replace))?;
+            }
+        }
+
+        if !self.trailer.is_empty() {
+            writeln!(f)?;
+            f.write_str(&self.trailer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a TOML document into sections, each starting with a table
+/// header. The first section is everything before the first header.
+fn split_sections(s: &str) -> Vec<&str> {
+    let mut sections = vec![];
+    let mut start = 0;
+    let mut offset = 0;
+
+    for line in s.split_inclusive('\n') {
+        if line.starts_with('[') && offset != 0 {
+            sections.push(&s[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+
+    sections.push(&s[start..]);
+    sections
+}
+
+fn is_package(section: &str) -> bool {
+    section.starts_with("[[package]]")
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"# This file is automatically @generated by Cargo.
+# It is not intended for manual editing.
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "rand 0.8.5",
+ "shared",
+]
+
+[[package]]
+name = "other"
+version = "0.1.0"
+dependencies = [
+ "rand 0.7.3",
+]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "6a6b1679d49b24bbfe0c803429aa1874472f50d9b363131f0e89fc356b544d03"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "34af8d1a0e25924bc5b7c43c4d4aa22ac8ef0ac2a9a1edcf3d2cc73b3b5b7b5f"
+
+[[package]]
+name = "shared"
+version = "0.1.0"
+"#;
+
+    #[test]
+    fn round_trips() {
+        let lock = CargoLock::parse(LOCKFILE).unwrap();
+
+        assert_eq!(LOCKFILE, lock.to_string());
+    }
+
+    #[test]
+    fn prunes_unreachable_packages() {
+        let mut lock = CargoLock::parse(LOCKFILE).unwrap();
+
+        lock.prune([("app", "0.1.0")]).unwrap();
+
+        let expected = r#"# This file is automatically @generated by Cargo.
+# It is not intended for manual editing.
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "rand",
+ "shared",
+]
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "34af8d1a0e25924bc5b7c43c4d4aa22ac8ef0ac2a9a1edcf3d2cc73b3b5b7b5f"
+
+[[package]]
+name = "shared"
+version = "0.1.0"
+"#;
+
+        assert_eq!(expected, lock.to_string());
+    }
+}
@@ -0,0 +1,358 @@
+use std::{collections::BTreeSet, env, fs};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::MetadataCommand;
+use log::*;
+
+use crate::{
+    create::is_stub,
+    lockfile::{load_lockfile, LOCKFILE_NAME},
+    workspace::find_root,
+};
+
+/// Directories within a profile directory that hold per-crate
+/// artifacts, named `<name>-<hash>`.
+pub(crate) const ARTIFACT_DIRS: &[&str] = &[".fingerprint", "build", "deps", "incremental"];
+
+#[derive(Debug, Default)]
+pub struct CleanOptions {
+    /// Path to the root manifest of the workspace
+    pub manifest_path: Option<Utf8PathBuf>,
+
+    /// Path to the target directory
+    pub target_dir: Option<Utf8PathBuf>,
+
+    /// Report what would be removed without removing anything
+    pub dry_run: bool,
+}
+
+/// Remove skeleton leftovers after the real sources have been copied
+/// over the skeleton.
+///
+/// Stubs that were not replaced by a real source file, the
+/// `Skeleton.lock`, and any artifacts and fingerprints Cargo recorded
+/// for workspace members are removed. Members whose source was kept
+/// in the skeleton are part of the dependency build and are left
+/// alone. Returns the removed paths.
+pub fn clean_skeleton(opts: CleanOptions) -> Result<Vec<Utf8PathBuf>> {
+    let root = find_root(opts.manifest_path.as_deref())?;
+
+    let lockfile = load_lockfile(&root)?;
+
+    let mut removed = vec![];
+
+    for stub in lockfile.stubs.iter() {
+        let path = root.join(stub);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+
+        if is_stub(&contents) {
+            removed.push(path);
+        }
+    }
+
+    // The metadata knows the target directory configured with
+    // `[build] target-dir`.
+    let metadata = MetadataCommand::new()
+        .manifest_path(root.join("Cargo.toml"))
+        .current_dir(&root)
+        .no_deps()
+        .exec()
+        .context("executing cargo metadata")?;
+
+    let target_dir = opts
+        .target_dir
+        .unwrap_or_else(|| metadata.target_directory.clone());
+
+    let stubbed: Vec<&str> = lockfile
+        .packages
+        .iter()
+        .filter(|pkg| !pkg.keep_source)
+        .map(|pkg| pkg.name.as_str())
+        .collect();
+
+    for profile_dir in profile_dirs(&target_dir) {
+        // Every artifact of a unit carries the unit's metadata hash,
+        // which is only known from the fingerprint directory named
+        // after the package. Matching on target names alone would
+        // also catch dependencies whose crate name a member reuses.
+        let hashes: BTreeSet<String> = stubbed
+            .iter()
+            .flat_map(|name| fingerprint_dirs(&profile_dir, name))
+            .filter_map(|dir| artifact_hash(dir.file_name()?).map(String::from))
+            .collect();
+
+        for dir in ARTIFACT_DIRS.iter().map(|dir| profile_dir.join(dir)) {
+            let entries = match dir.read_dir_utf8() {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                if artifact_hash(entry.file_name()).is_some_and(|hash| hashes.contains(hash)) {
+                    removed.push(entry.into_path());
+                }
+            }
+        }
+    }
+
+    removed.sort();
+    removed.push(root.join(LOCKFILE_NAME));
+
+    if opts.dry_run {
+        return Ok(removed);
+    }
+
+    for path in removed.iter() {
+        debug!("Removing {}", path);
+
+        if path.is_dir() {
+            fs::remove_dir_all(path).context(format!("removing {}", path))?;
+        } else {
+            fs::remove_file(path).context(format!("removing {}", path))?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Returns the fingerprint directories Cargo recorded for a package
+/// in a profile directory, named `<package>-<hash>`. There is one for
+/// each unit of the package, e.g. its library, its binaries and its
+/// build script.
+pub(crate) fn fingerprint_dirs(profile_dir: &Utf8Path, package_name: &str) -> Vec<Utf8PathBuf> {
+    let entries = match profile_dir.join(".fingerprint").read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut dirs: Vec<Utf8PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            let file_name = entry.file_name();
+            artifact_hash(file_name).is_some_and(|hash| {
+                file_name.len() == package_name.len() + hash.len() + 1
+                    && file_name.starts_with(package_name)
+            })
+        })
+        .map(|entry| entry.into_path())
+        .collect();
+
+    dirs.sort();
+    dirs
+}
+
+/// Returns the target directory, defaulting to `CARGO_TARGET_DIR`
+/// or `target` in the workspace root.
+pub(crate) fn target_dir(root: &Utf8Path, target_dir: Option<Utf8PathBuf>) -> Utf8PathBuf {
+    target_dir
+        .or_else(|| env::var("CARGO_TARGET_DIR").ok().map(|dir| root.join(dir)))
+        .unwrap_or_else(|| root.join("target"))
+}
+
+/// Find the profile directories in a target directory, e.g.
+/// `target/release` or `target/x86_64-unknown-linux-gnu/debug`.
+pub(crate) fn profile_dirs(target_dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let mut dirs = vec![];
+
+    let subdirs = |dir: &Utf8Path| -> Vec<Utf8PathBuf> {
+        dir.read_dir_utf8()
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.into_path())
+            .filter(|path| path.is_dir())
+            .collect()
+    };
+
+    for dir in subdirs(target_dir) {
+        if dir.join(".fingerprint").is_dir() {
+            dirs.push(dir);
+            continue;
+        }
+
+        dirs.extend(
+            subdirs(&dir)
+                .into_iter()
+                .filter(|dir| dir.join(".fingerprint").is_dir()),
+        );
+    }
+
+    dirs.sort();
+    dirs
+}
+
+/// Returns the metadata hash of an artifact, i.e. the `<hash>` of
+/// `<name>-<hash>` or `lib<name>-<hash>` with an optional extension.
+pub(crate) fn artifact_hash(file_name: &str) -> Option<&str> {
+    let stem = file_name.split('.').next().unwrap_or_default();
+    let (_, hash) = stem.rsplit_once('-')?;
+
+    if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hash)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn matches_artifacts() {
+        assert_eq!(
+            Some("0123456789abcdef"),
+            artifact_hash("my-app-0123456789abcdef")
+        );
+        assert_eq!(
+            Some("0123456789abcdef"),
+            artifact_hash("libmy_app-0123456789abcdef.rlib")
+        );
+        assert_eq!(
+            Some("0123456789abcdef"),
+            artifact_hash("my_app-0123456789abcdef.d")
+        );
+        assert_eq!(None, artifact_hash("my-app-extra"));
+        assert_eq!(None, artifact_hash("my-app"));
+    }
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn removes_renamed_target_artifacts() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "my-app"
+            version = "0.1.0"
+
+            [lib]
+            name = "app_core"
+
+            [[bin]]
+            name = "app-server"
+            path = "src/main.rs"
+            "#,
+        );
+        write(root, "src/lib.rs", "");
+        write(root, "src/main.rs", "fn main() {}");
+        write(
+            root,
+            ".cargo/config.toml",
+            "[build]\ntarget-dir = \"out\"\n",
+        );
+        write(
+            root,
+            LOCKFILE_NAME,
+            "stubs = []\n\n[[package]]\nname = \"my-app\"\nid = \"my-app\"\ndependencies = []\n",
+        );
+
+        let artifacts = [
+            "out/debug/.fingerprint/my-app-0123456789abcdef",
+            "out/debug/.fingerprint/my-app-1111111111111111",
+            "out/debug/deps/libapp_core-0123456789abcdef.rlib",
+            "out/debug/deps/app_server-1111111111111111",
+            "out/debug/.fingerprint/serde-2222222222222222",
+            "out/debug/deps/libserde-2222222222222222.rlib",
+        ];
+
+        for path in artifacts {
+            write(root, path, "");
+        }
+
+        let removed = clean_skeleton(CleanOptions {
+            manifest_path: Some(root.join("Cargo.toml")),
+            dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let removed: Vec<&str> = removed
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().as_str())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "out/debug/.fingerprint/my-app-0123456789abcdef",
+                "out/debug/.fingerprint/my-app-1111111111111111",
+                "out/debug/deps/app_server-1111111111111111",
+                "out/debug/deps/libapp_core-0123456789abcdef.rlib",
+                LOCKFILE_NAME,
+            ],
+            removed
+        );
+    }
+
+    #[test]
+    fn keeps_dependency_with_member_crate_name() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "my-app"
+            version = "0.1.0"
+
+            [lib]
+            name = "serde"
+            "#,
+        );
+        write(root, "src/lib.rs", "");
+        write(
+            root,
+            LOCKFILE_NAME,
+            "stubs = []\n\n[[package]]\nname = \"my-app\"\nid = \"my-app\"\ndependencies = []\n",
+        );
+
+        let artifacts = [
+            "target/debug/.fingerprint/my-app-0123456789abcdef",
+            "target/debug/deps/libserde-0123456789abcdef.rlib",
+            "target/debug/.fingerprint/serde-2222222222222222",
+            "target/debug/deps/libserde-2222222222222222.rlib",
+        ];
+
+        for path in artifacts {
+            write(root, path, "");
+        }
+
+        let removed = clean_skeleton(CleanOptions {
+            manifest_path: Some(root.join("Cargo.toml")),
+            dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let removed: Vec<&str> = removed
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().as_str())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "target/debug/.fingerprint/my-app-0123456789abcdef",
+                "target/debug/deps/libserde-0123456789abcdef.rlib",
+                LOCKFILE_NAME,
+            ],
+            removed
+        );
+    }
+}
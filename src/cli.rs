@@ -1,12 +1,32 @@
-use anyhow::{Context, Result};
+use std::{env, io, time::Instant};
+
+use anyhow::{bail, Context, Result};
 use camino::Utf8PathBuf;
-use clap::{Args, CommandFactory, Parser};
-use clap_cargo::style::{GOOD, CLAP_STYLING};
+use cargo_metadata::CargoOpt;
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgAction, ArgMatches, Args, ColorChoice, Command,
+    CommandFactory, FromArgMatches, Parser,
+};
+use clap_cargo::style::{CLAP_STYLING, ERROR, GOOD, WARN};
+use clap_complete::{ArgValueCompleter, CompleteEnv};
 
 use crate::{
-    build::{build_skeleton_package, BuildOptions},
-    create::create_skeleton,
+    audit::{audit_build, AuditOptions},
+    build::{
+        build_skeleton_archive, build_skeleton_variants, plan_skeleton_build, BuildOptions,
+        CargoCommand,
+    },
+    clean::{clean_skeleton, CleanOptions},
+    complete::{complete_packages, write_registration, COMPLETE_VAR},
+    create::{create_skeleton, CreateOptions, DEFAULT_OUT_PATH},
+    dockerfile::{
+        dockerignore_path, generate_dockerfile, write_docker_files, DockerfileOptions,
+        DEFAULT_RUNTIME_IMAGE, DEFAULT_SKELETON_IMAGE,
+    },
+    settings::Settings,
+    shell::{Event, MessageFormat, Shell, Verbosity},
     unpack::{unpack_skeleton_archive, UnpackOptions},
+    verify::{verify_sources, VerifySourcesOptions},
 };
 
 #[derive(Debug, Parser)]
@@ -16,10 +36,60 @@ use crate::{
 #[command(version, about, long_about = None)]
 pub enum Cli {
     /// Create and build skeleton packages
+    Skeleton(SkeletonArgs),
+}
+
+#[derive(Debug, Args)]
+#[command(after_long_help = EXIT_STATUS_HELP)]
+pub struct SkeletonArgs {
+    /// Use verbose output (-vv very verbose)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Do not print status messages
+    #[arg(short, long, global = true, env = "CARGO_SKELETON_QUIET")]
+    quiet: bool,
+
+    /// Coloring: auto, always, never
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t, env = "CARGO_SKELETON_COLOR")]
+    color: ColorChoice,
+
+    /// Output format for status messages
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FMT",
+        default_value_t,
+        env = "CARGO_SKELETON_MESSAGE_FORMAT"
+    )]
+    message_format: MessageFormat,
+
     #[command(subcommand)]
-    Skeleton(SkeletonCommand),
+    command: SkeletonCommand,
 }
 
+/// Help text describing the environment variables of a command.
+const ENV_HELP: &str = "\
+Environment:
+  Every option may also be set with the `CARGO_SKELETON_*` environment
+  variable shown in its description. List options accept comma-separated
+  values. Options given on the command line take precedence over the
+  environment, which takes precedence over `[workspace.metadata.skeleton]`
+  and the built-in defaults.";
+
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  Success
+  1  Other error, e.g. `dockerfile --check` found outdated files
+  2  Invalid command line arguments
+  3  The workspace has no Skeleton.lock
+  4  The Skeleton.lock is invalid
+  5  The skeleton archive does not exist
+  6  Unpacking would overwrite an existing workspace
+  7  A package ID specification did not match any packages
+  8  A cargo command failed";
+
 #[derive(Debug, Parser)]
 pub enum SkeletonCommand {
     /// Create a skeleton archive from a Cargo workspace
@@ -29,6 +99,20 @@ pub enum SkeletonCommand {
     /// Files that affect compilation are copied as-is, while
     /// targets are replaced with empty stub files.
     /// 
+    /// Cargo config and toolchain files are collected from the
+    /// workspace root and every directory between the root and
+    /// a member, along with any config files they `include`.
+    /// Local files referenced by the config, such as linker
+    /// scripts, linker wrappers, relative `[env]` paths, and
+    /// build script override search paths, are also copied.
+    /// 
+    /// Cargo credentials files are never added to the archive.
+    /// If a config file contains tokens, passwords, URLs with
+    /// credentials, or credential providers given arguments,
+    /// creating the archive fails. Pass
+    /// `--scrub-secrets` to remove those keys from the
+    /// archived copy instead.
+    /// 
     /// The skeleton archive is written to `skeleton.tar` in
     /// the current directory by default. To change the path,
     /// use the `--out-path` option.
@@ -39,6 +123,52 @@ pub enum SkeletonCommand {
     /// directory and any parent directories. To specify a
     /// different path, use the `--manifest-path` option.
     /// 
+    /// To create a smaller skeleton for a single package, use
+    /// the `--package` option. The archive will only contain the
+    /// selected packages and the workspace members they depend
+    /// on. The root manifest is rewritten to list just those
+    /// members, and `Cargo.lock` is pruned to the packages they
+    /// use, so the archive only changes when one of them does.
+    /// 
+    /// The `--exclude` option leaves packages out of a skeleton
+    /// for the rest of the workspace, e.g. tooling crates whose
+    /// dependencies change often. `Cargo.lock` is pruned the same
+    /// way. Excluded packages are still included if a selected
+    /// package depends on them.
+    /// 
+    /// Workspace members that rarely change but are slow to
+    /// compile can be kept with the `--keep-source` option, or by
+    /// setting `package.metadata.skeleton.keep-source = true` in
+    /// their manifest. Their complete source is added instead of
+    /// stubs, respecting the manifest's `include` and `exclude`
+    /// lists, and `cargo skeleton build` compiles them along with
+    /// the dependencies. Members they depend on must be kept too.
+    /// 
+    /// Some dependency builds need files the skeleton can't
+    /// know about, such as an offline query cache or a `.env`
+    /// file read by a build script. Use `--include-files` to
+    /// add files matching a glob, relative to the workspace
+    /// root. Use `--exclude-files` to leave matching files out.
+    /// Manifests and target stubs can't be replaced.
+    /// 
+    /// Pass `--verify` to check the archive before shipping it.
+    /// The archive is unpacked into a temporary directory and
+    /// resolved with `cargo metadata --locked --offline`, and the
+    /// dependencies of every package must match the ones recorded
+    /// in `Skeleton.lock`. A missing manifest, config file, or
+    /// path dependency then fails the create step instead of the
+    /// dependency build.
+    /// 
+    /// Defaults for these options may be set in the
+    /// `[workspace.metadata.skeleton]` table of the root
+    /// manifest, e.g. `out-path`, `features`, `packages`,
+    /// `exclude`, `keep-source`, `include-files`,
+    /// `exclude-files`, `scrub-secrets`, and `verify`. Options
+    /// given on the command line or in the environment take
+    /// precedence. Use `--no-scrub-secrets` and `--no-verify` to
+    /// turn off configured flags, and any feature selection flag
+    /// to replace the configured features.
+    /// 
     /// Package dependencies are resolved when the archive
     /// is created. The feature selection flags may be used
     /// to control which features are enabled when Cargo
@@ -65,8 +195,141 @@ pub enum SkeletonCommand {
     Unpack(UnpackArgs),
     /// Compile a skeleton package's dependencies
     /// 
-    /// TODO(MJA): LONG HELP
+    /// Reads the `Skeleton.lock` in the workspace root and runs
+    /// `cargo build` with a `--package` flag for each dependency
+    /// of the selected packages. The packages themselves are
+    /// never compiled.
+    /// 
+    /// All packages are built by default. Use the `--package`,
+    /// `--exclude`, and `--all` flags to select packages.
+    /// Anything after `--` is passed to `cargo build` as-is.
+    /// 
+    /// Dependencies compiled by `cargo build` are not reused by
+    /// other cargo commands. Use `--cargo-command` to compile them
+    /// with `check`, `clippy`, `doc`, or `test` to match the final
+    /// command. `test` builds the dependencies, and the tests of
+    /// members with kept source, without building the tests of the
+    /// dependencies themselves. Dev-dependencies are not recorded
+    /// in the skeleton and are still compiled by the final
+    /// `cargo test`, as are dependencies whose features they
+    /// change.
+    /// 
+    /// Artifacts are only reused if the final build runs the
+    /// same toolchain invocation. Use `--wrapper` to run another
+    /// program instead of cargo, e.g. `cross`, and `--subcommand`
+    /// to run a cargo plugin, e.g. `zigbuild` or `auditable build`.
+    /// Without `--wrapper`, the cargo binary is read from the
+    /// `CARGO` environment variable.
+    /// 
+    /// The `--profile` and `--target` options may be repeated to
+    /// build the dependencies for every combination of profiles
+    /// and targets. A failed combination does not stop the others,
+    /// and each one is reported as finished or failed.
+    /// 
+    /// Finished builds are recorded in `skeleton-build.json` in
+    /// the target directory, along with a digest of the
+    /// `Skeleton.lock`, the manifests, the Cargo config files, the
+    /// `RUSTFLAGS`, `CARGO_PROFILE_*`, `CARGO_BUILD_*`, and
+    /// `CARGO_TARGET_*` environment variables, the cargo commands,
+    /// and the `rustc -vV` output. Combinations whose digest did not change are
+    /// reported as fresh and not built again. Use `--force` to
+    /// build them anyway.
+    /// 
+    /// Pass `--archive` to unpack a skeleton archive into the
+    /// workspace root first, with the same checks as `cargo
+    /// skeleton unpack`. The build only starts once the whole
+    /// archive was unpacked, so a single step can do both.
+    /// 
+    /// To see what would be run, use `--dry-run` to print the
+    /// cargo commands as a shell script, or `--print-plan` to
+    /// print them as JSON. Nothing is built in either case.
+    /// 
+    /// Defaults for the package selection and cargo arguments
+    /// may be set in the `[workspace.metadata.skeleton.build]`
+    /// table of the root manifest, using the `packages`,
+    /// `exclude`, `cargo-command`, `wrapper`, `subcommand`,
+    /// `profiles`, `targets`, and `args` keys. Options
+    /// given on the command line or in the environment take
+    /// precedence.
     Build(BuildArgs),
+    /// Remove skeleton leftovers after the real build
+    /// 
+    /// Run this after copying the real sources over an unpacked
+    /// skeleton. Target stubs that were not replaced by a real
+    /// source file are removed, along with the `Skeleton.lock`.
+    /// 
+    /// Artifacts and fingerprints Cargo recorded for workspace
+    /// members are removed from the target directory, so a
+    /// stale stub build can never be mistaken for a fresh one.
+    /// Members whose source was kept in the skeleton are left
+    /// alone. Dependency artifacts are never removed.
+    /// 
+    /// Every removed path is reported. Use `--dry-run` to see
+    /// what would be removed without removing anything.
+    Clean(CleanArgs),
+    /// Report dependencies recompiled by the final build
+    /// 
+    /// Runs `cargo build --message-format=json` and checks the
+    /// `fresh` flag of every compiler artifact. Dependencies
+    /// that were compiled instead of reused from the skeleton
+    /// build are listed, and the command fails if there are any.
+    /// Workspace members are ignored.
+    /// 
+    /// Anything after `--` is passed to `cargo build` as-is, and
+    /// should match the flags used for the final build.
+    /// 
+    /// To audit a build that already ran, save its output with
+    /// `--message-format=json` and pass the file with `--input`,
+    /// or `--input -` to read it from stdin.
+    Audit(AuditArgs),
+    /// Check that no skeleton stubs are left in the workspace
+    /// 
+    /// Run this after copying the real sources over an unpacked
+    /// skeleton. The source file of every target of every
+    /// workspace member is compared with the skeleton stubs, and
+    /// the command fails if any stub is still in place.
+    /// 
+    /// With `--check-artifacts`, the fingerprints Cargo recorded
+    /// for each member must also be newer than the member's
+    /// sources, which catches artifacts left over from compiling
+    /// the stubs.
+    VerifySources(VerifySourcesArgs),
+    /// Generate a Dockerfile building the workspace with a skeleton
+    ///
+    /// Writes a multi-stage Dockerfile to the workspace root,
+    /// along with a matching `.dockerignore`. The
+    /// `skeleton-builder` stage creates the skeleton archive, the
+    /// `builder` stage builds the dependencies from the skeleton
+    /// and then the real sources, and the `runtime` stage copies
+    /// the binaries into `--runtime-image`.
+    /// 
+    /// Every workspace member with a binary target is built,
+    /// unless packages are selected with `--package`. The Rust
+    /// image matches the version pinned in `rust-toolchain.toml`
+    /// or `rust-toolchain`, and any other toolchain file is
+    /// installed with rustup.
+    /// 
+    /// The output only depends on the workspace and the given
+    /// options, so the files can be checked in. With `--check`,
+    /// nothing is written and the command fails if either file
+    /// is out of date.
+    Dockerfile(DockerfileArgs),
+    /// Generate shell completions
+    ///
+    /// Prints a script that registers completions for the
+    /// `cargo-skeleton` binary, e.g. `cargo-skeleton skeleton build`,
+    /// in the given shell, or the shell named by `$SHELL`. The
+    /// script calls back into this binary for every completion, so
+    /// package names given to `--package`, `--exclude`, and
+    /// `--keep-source` are completed from `Skeleton.lock` in a
+    /// skeleton, or from the workspace members otherwise.
+    /// 
+    /// The script is only registered for `cargo-skeleton`, so the
+    /// completions of `cargo` itself are left untouched. Load it
+    /// on shell startup rather than saving it, so it is
+    /// regenerated after upgrading, e.g. with
+    /// `source <(cargo skeleton completions bash)` in `~/.bashrc`.
+    Completions(CompletionsArgs),
     /// Generate man pages
     #[command(hide = true)]
     Mangen(MangenArgs),
@@ -74,6 +337,7 @@ pub enum SkeletonCommand {
 
 #[derive(Debug, Args)]
 #[command(version, about, long_about = None)]
+#[command(after_long_help = ENV_HELP)]
 pub struct CreateArgs {
     #[clap(flatten)]
     manifest: clap_cargo::Manifest,
@@ -81,25 +345,82 @@ pub struct CreateArgs {
     #[clap(flatten)]
     features: clap_cargo::Features,
 
-    /// Path to write the skeleton archive to
-    #[arg(long, default_value_t = Utf8PathBuf::from("skeleton.tar"))]
-    out_path: Utf8PathBuf,
+    /// Path to write the skeleton archive to [default: skeleton.tar]
+    #[arg(long, value_name = "PATH", env = "CARGO_SKELETON_OUT_PATH")]
+    out_path: Option<Utf8PathBuf>,
+
+    /// Strip secrets from config files instead of failing
+    #[arg(long, overrides_with = "no_scrub_secrets", env = "CARGO_SKELETON_SCRUB_SECRETS")]
+    scrub_secrets: bool,
+
+    /// Fail on secrets in config files, even if `scrub-secrets` is configured
+    #[arg(long, overrides_with = "scrub_secrets", env = "CARGO_SKELETON_NO_SCRUB_SECRETS")]
+    no_scrub_secrets: bool,
+
+    /// Only include the given packages and the members they depend on
+    #[arg(
+        short,
+        long = "package",
+        value_name = "SPEC",
+        value_delimiter = ',',
+        env = "CARGO_SKELETON_PACKAGE",
+        add = ArgValueCompleter::new(complete_packages)
+    )]
+    packages: Vec<String>,
+
+    /// Leave the given packages out of the skeleton
+    #[arg(
+        long,
+        value_name = "SPEC",
+        value_delimiter = ',',
+        env = "CARGO_SKELETON_EXCLUDE",
+        add = ArgValueCompleter::new(complete_packages)
+    )]
+    exclude: Vec<String>,
+
+    /// Keep the complete source of the given packages
+    #[arg(
+        long,
+        value_name = "SPEC",
+        value_delimiter = ',',
+        env = "CARGO_SKELETON_KEEP_SOURCE",
+        add = ArgValueCompleter::new(complete_packages)
+    )]
+    keep_source: Vec<String>,
+
+    /// Add files matching the glob to the archive as-is
+    #[arg(long, value_name = "GLOB", env = "CARGO_SKELETON_INCLUDE_FILES")]
+    include_files: Vec<String>,
+
+    /// Leave files matching the glob out of the archive
+    #[arg(long, value_name = "GLOB", env = "CARGO_SKELETON_EXCLUDE_FILES")]
+    exclude_files: Vec<String>,
+
+    /// Check that the written archive resolves offline
+    #[arg(long, overrides_with = "no_verify", env = "CARGO_SKELETON_VERIFY")]
+    verify: bool,
+
+    /// Do not check the written archive, even if `verify` is configured
+    #[arg(long, overrides_with = "verify", env = "CARGO_SKELETON_NO_VERIFY")]
+    no_verify: bool,
 }
 
 #[derive(Debug, Args)]
 #[command(version, about, long_about = None)]
+#[command(after_long_help = ENV_HELP)]
 pub struct UnpackArgs {
-    /// Path to the skeleton archive
-    #[arg(long, default_value_t = Utf8PathBuf::from("skeleton.tar"))]
-    archive_path: Utf8PathBuf,
+    /// Path to the skeleton archive [default: skeleton.tar]
+    #[arg(long, value_name = "PATH", env = "CARGO_SKELETON_ARCHIVE_PATH")]
+    archive_path: Option<Utf8PathBuf>,
 
     /// Output path for the archive contents
-    #[arg(long, default_value_t = Utf8PathBuf::from("."))]
+    #[arg(long, default_value_t = Utf8PathBuf::from("."), env = "CARGO_SKELETON_DEST_PATH")]
     out_path: Utf8PathBuf,
 }
 
 #[derive(Debug, Args)]
 #[command(version, about, long_about = None)]
+#[command(after_long_help = ENV_HELP)]
 pub struct BuildArgs {
     #[clap(flatten)]
     manifest: clap_cargo::Manifest,
@@ -107,11 +428,181 @@ pub struct BuildArgs {
     #[clap(flatten)]
     workspace: clap_cargo::Workspace,
 
+    /// Unpack the skeleton archive before building
+    #[arg(long, value_name = "PATH", env = "CARGO_SKELETON_BUILD_ARCHIVE")]
+    archive: Option<Utf8PathBuf>,
+
+    /// Cargo command to compile the dependencies with [default: build]
+    #[arg(long, value_enum, value_name = "COMMAND", env = "CARGO_SKELETON_CARGO_COMMAND")]
+    cargo_command: Option<CargoCommandArg>,
+
+    /// Program to run instead of cargo, e.g. `cross`
+    #[arg(long, value_name = "PROGRAM", env = "CARGO_SKELETON_WRAPPER")]
+    wrapper: Option<String>,
+
+    /// Space-separated subcommand to run instead of the cargo command
+    #[arg(
+        long,
+        value_name = "WORDS",
+        value_delimiter = ' ',
+        env = "CARGO_SKELETON_SUBCOMMAND"
+    )]
+    subcommand: Vec<String>,
+
+    /// Build the dependencies with the given profile, may be repeated
+    #[arg(long = "profile", value_name = "PROFILE-NAME", value_delimiter = ',', env = "CARGO_SKELETON_PROFILE")]
+    profiles: Vec<String>,
+
+    /// Build the dependencies for the given target triple, may be repeated
+    #[arg(long = "target", value_name = "TRIPLE", value_delimiter = ',', env = "CARGO_SKELETON_TARGET")]
+    targets: Vec<String>,
+
+    /// Build even if the build stamp is up to date
+    #[arg(long, env = "CARGO_SKELETON_FORCE")]
+    force: bool,
+
+    /// Print the commands as a shell script instead of running them
+    #[arg(long, env = "CARGO_SKELETON_DRY_RUN")]
+    dry_run: bool,
+
+    /// Print the commands as JSON instead of running them
+    #[arg(long, env = "CARGO_SKELETON_PRINT_PLAN")]
+    print_plan: bool,
+
+    /// Additional cargo build arguments
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+/// The values of `--cargo-command`, kept out of the library's
+/// [`CargoCommand`] so it does not depend on clap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CargoCommandArg {
+    /// `cargo build`
+    Build,
+    /// `cargo check`
+    Check,
+    /// `cargo clippy`
+    Clippy,
+    /// `cargo doc`
+    Doc,
+    /// `cargo build`, with `--tests` for members with kept source
+    Test,
+}
+
+impl From<CargoCommandArg> for CargoCommand {
+    fn from(arg: CargoCommandArg) -> Self {
+        match arg {
+            CargoCommandArg::Build => CargoCommand::Build,
+            CargoCommandArg::Check => CargoCommand::Check,
+            CargoCommandArg::Clippy => CargoCommand::Clippy,
+            CargoCommandArg::Doc => CargoCommand::Doc,
+            CargoCommandArg::Test => CargoCommand::Test,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+#[command(version, about, long_about = None)]
+pub struct CleanArgs {
+    #[clap(flatten)]
+    manifest: clap_cargo::Manifest,
+
+    /// Directory for all generated artifacts
+    #[arg(long, value_name = "DIRECTORY")]
+    target_dir: Option<Utf8PathBuf>,
+
+    /// Report what would be removed without removing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(version, about, long_about = None)]
+pub struct AuditArgs {
+    #[clap(flatten)]
+    manifest: clap_cargo::Manifest,
+
+    /// Read JSON build output from a file instead of building
+    #[arg(long, value_name = "PATH")]
+    input: Option<Utf8PathBuf>,
+
     /// Additional cargo build arguments
     #[arg(last = true)]
     args: Vec<String>,
 }
 
+#[derive(Debug, Args)]
+#[command(version, about, long_about = None)]
+pub struct VerifySourcesArgs {
+    #[clap(flatten)]
+    manifest: clap_cargo::Manifest,
+
+    /// Check that member artifacts are newer than their sources
+    #[arg(long)]
+    check_artifacts: bool,
+
+    /// Directory for all generated artifacts
+    #[arg(long, value_name = "DIRECTORY")]
+    target_dir: Option<Utf8PathBuf>,
+}
+
+#[derive(Debug, Args)]
+#[command(version, about, long_about = None)]
+pub struct DockerfileArgs {
+    #[clap(flatten)]
+    manifest: clap_cargo::Manifest,
+
+    #[clap(flatten)]
+    features: clap_cargo::Features,
+
+    /// Only build the given binary packages
+    #[arg(
+        short,
+        long = "package",
+        value_name = "SPEC",
+        value_delimiter = ',',
+        add = ArgValueCompleter::new(complete_packages)
+    )]
+    packages: Vec<String>,
+
+    /// Build with the given profile [default: release]
+    #[arg(long, value_name = "PROFILE-NAME")]
+    profile: Option<String>,
+
+    /// Build for the given target triple
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Image for the build stages [default: from the toolchain file]
+    #[arg(long, value_name = "IMAGE")]
+    base_image: Option<String>,
+
+    /// Image the binaries are copied into
+    #[arg(long, value_name = "IMAGE", default_value = DEFAULT_RUNTIME_IMAGE)]
+    runtime_image: String,
+
+    /// Image to copy the cargo-skeleton binary from
+    #[arg(long, value_name = "IMAGE", default_value = DEFAULT_SKELETON_IMAGE)]
+    skeleton_image: String,
+
+    /// Path to write the Dockerfile to [default: Dockerfile in the workspace root]
+    #[arg(long, value_name = "PATH")]
+    out_path: Option<Utf8PathBuf>,
+
+    /// Check that the files are up to date instead of writing them
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(version, about, long_about = None)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for [default: $SHELL]
+    #[arg(value_enum)]
+    shell: Option<clap_complete::Shell>,
+}
+
 #[derive(Debug, Args)]
 #[command(hide = true)]
 pub struct MangenArgs {
@@ -120,51 +611,537 @@ pub struct MangenArgs {
     out_path: Utf8PathBuf,
 }
 
+/// Returns the command line definition, with environment variables
+/// for the flattened Cargo args and package name completions.
+pub fn command() -> Command {
+    let manifest = |cmd: Command| {
+        cmd.mut_arg("PATH", |arg| arg.env("CARGO_SKELETON_MANIFEST_PATH"))
+    };
+    let packages = || ArgValueCompleter::new(complete_packages);
+
+    Cli::command().mut_subcommand("skeleton", |cmd| {
+        cmd.mut_subcommand("create", |cmd| {
+            manifest(cmd)
+                .mut_arg("features", |arg| arg.env("CARGO_SKELETON_FEATURES"))
+                .mut_arg("all_features", |arg| arg.env("CARGO_SKELETON_ALL_FEATURES"))
+                .mut_arg("no_default_features", |arg| {
+                    arg.env("CARGO_SKELETON_NO_DEFAULT_FEATURES")
+                })
+        })
+        .mut_subcommand("build", |cmd| {
+            manifest(cmd)
+                .mut_arg("package", |arg| {
+                    arg.env("CARGO_SKELETON_PACKAGE")
+                        .value_delimiter(',')
+                        .add(packages())
+                })
+                .mut_arg("exclude", |arg| {
+                    arg.env("CARGO_SKELETON_EXCLUDE")
+                        .value_delimiter(',')
+                        .add(packages())
+                })
+                .mut_arg("workspace", |arg| arg.env("CARGO_SKELETON_WORKSPACE"))
+        })
+    })
+}
+
+/// Parse the command line arguments and environment variables.
+///
+/// When called back by a completion script, prints the completions
+/// and exits instead.
+pub fn parse() -> Cli {
+    CompleteEnv::with_factory(command).var(COMPLETE_VAR).complete();
+
+    let matches = command().get_matches();
+
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    resolve_conflicts(&mut cli, &matches).unwrap_or_else(|err| err.exit());
+
+    cli
+}
+
+/// Drop arguments taken from the environment that conflict with
+/// arguments given on the command line.
+///
+/// Clap cannot tell where a value came from when it checks for
+/// conflicts, so e.g. `CARGO_SKELETON_CARGO_COMMAND` would make
+/// `--subcommand` fail. Conflicting arguments that both come from the
+/// command line, or both from the environment, are still an error.
+fn resolve_conflicts(cli: &mut Cli, matches: &ArgMatches) -> Result<(), clap::Error> {
+    let Cli::Skeleton(args) = cli;
+    let matches = match matches.subcommand_matches("skeleton") {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    if let Keep::Second = conflict(
+        matches,
+        &["skeleton"],
+        ("quiet", args.quiet),
+        ("verbose", args.verbose > 0),
+    )? {
+        args.quiet = false;
+    }
+
+    let (args, matches) = match (&mut args.command, matches.subcommand_matches("build")) {
+        (SkeletonCommand::Build(args), Some(matches)) => (args, matches),
+        _ => return Ok(()),
+    };
+
+    match conflict(
+        matches,
+        &["skeleton", "build"],
+        ("cargo_command", args.cargo_command.is_some()),
+        ("subcommand", !args.subcommand.is_empty()),
+    )? {
+        Keep::First => args.subcommand.clear(),
+        Keep::Second => args.cargo_command = None,
+        Keep::Both => {}
+    }
+
+    match conflict(
+        matches,
+        &["skeleton", "build"],
+        ("archive", args.archive.is_some()),
+        ("dry_run", args.dry_run),
+    )? {
+        Keep::First => args.dry_run = false,
+        Keep::Second => args.archive = None,
+        Keep::Both => {}
+    }
+
+    match conflict(
+        matches,
+        &["skeleton", "build"],
+        ("archive", args.archive.is_some()),
+        ("print_plan", args.print_plan),
+    )? {
+        Keep::First => args.print_plan = false,
+        Keep::Second => args.archive = None,
+        Keep::Both => {}
+    }
+
+    match conflict(
+        matches,
+        &["skeleton", "build"],
+        ("dry_run", args.dry_run),
+        ("print_plan", args.print_plan),
+    )? {
+        Keep::First => args.print_plan = false,
+        Keep::Second => args.dry_run = false,
+        Keep::Both => {}
+    }
+
+    Ok(())
+}
+
+/// Returns the definition of a subcommand, for formatting errors
+/// with its usage.
+fn subcommand(path: &[&str]) -> Command {
+    let mut cmd = command();
+    cmd.build();
+
+    path.iter().fold(cmd, |cmd, name| {
+        cmd.find_subcommand(name)
+            .expect("subcommand is defined")
+            .clone()
+    })
+}
+
+/// Which of two conflicting arguments to keep.
+enum Keep {
+    Both,
+    First,
+    Second,
+}
+
+/// Returns which of two arguments to keep if both are set, given by
+/// their ID and whether they are set: the one from the command line
+/// if the other one comes from the environment.
+fn conflict(
+    matches: &ArgMatches,
+    path: &[&str],
+    (first, first_set): (&str, bool),
+    (second, second_set): (&str, bool),
+) -> Result<Keep, clap::Error> {
+    if !first_set || !second_set {
+        return Ok(Keep::Both);
+    }
+
+    let from_env = |id| matches.value_source(id) == Some(ValueSource::EnvVariable);
+
+    match (from_env(first), from_env(second)) {
+        (false, true) => Ok(Keep::First),
+        (true, false) => Ok(Keep::Second),
+        _ => Err(clap::Error::raw(
+            ErrorKind::ArgumentConflict,
+            format!(
+                "the argument '--{}' cannot be used with '--{}'",
+                first.replace('_', "-"),
+                second.replace('_', "-")
+            ),
+        )
+        .format(&mut subcommand(path))),
+    }
+}
+
 pub fn run(cli: Cli) -> Result<()> {
-    let cmd = match cli {
-        Cli::Skeleton(cmd) => cmd,
+    let Cli::Skeleton(args) = cli;
+
+    let verbosity = match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, count) => Verbosity::Verbose(count),
     };
 
+    let shell = Shell::new(args.message_format, verbosity, args.color);
+    shell.init();
+
+    let result = run_command(args.command, &shell);
+
+    if let Err(err) = &result {
+        shell.event(Event::Error {
+            message: format!("{:#}", err),
+        });
+    }
+
+    result
+}
+
+fn run_command(cmd: SkeletonCommand, shell: &Shell) -> Result<()> {
+    let start = Instant::now();
+
     match cmd {
         SkeletonCommand::Create(args) => {
+            // Settings are read from the root manifest of the
+            // workspace Cargo finds, which may be in a parent
+            // directory.
+            let settings = {
+                let metadata = args
+                    .manifest
+                    .metadata()
+                    .no_deps()
+                    .exec()
+                    .context("executing cargo metadata")?;
+
+                Settings::from_metadata(&metadata)?
+            };
+
             let metadata = {
                 let mut metadata = args.manifest.metadata();
 
-                args.features.forward_metadata(&mut metadata);
+                for opt in feature_opts(args.features.clone(), &settings) {
+                    metadata.features(opt);
+                }
 
                 metadata.exec().context("executing cargo metadata")?
             };
 
-            println!("{GOOD}Creating{GOOD:#} {}", args.out_path);
-            create_skeleton(metadata, args.out_path).context("building skeleton")?;
-            println!("{GOOD}Finished{GOOD:#}");
+            let opts = create_options(args, settings);
+            let out_path = opts.out_path.clone().unwrap_or_default();
+
+            shell.status(GOOD, "Creating", &out_path);
+            let report = create_skeleton(metadata, opts).context("building skeleton")?;
+
+            for name in report.packages.iter() {
+                shell.event(Event::PackageSelected { name });
+            }
+            for path in report.entries.iter() {
+                shell.event(Event::EntryAdded { path });
+            }
+            shell.event(Event::ArchiveCreated {
+                path: &out_path,
+                size: report.size,
+                digest: &report.digest,
+                packages: report.packages.len(),
+                entries: report.entries.len(),
+                duration_ms: start.elapsed().as_millis(),
+            });
+
+            shell.status(GOOD, "Finished", "");
         }
         SkeletonCommand::Unpack(args) => {
+            let archive_path = args
+                .archive_path
+                .unwrap_or_else(|| DEFAULT_OUT_PATH.into());
+
             let opts = UnpackOptions {
-                archive_path: Some(args.archive_path.clone()),
-                dest_path: Some(args.out_path),
+                archive_path: Some(archive_path.clone()),
+                dest_path: Some(args.out_path.clone()),
             };
 
-            println!("{GOOD}Unpacking{GOOD:#} {}", args.archive_path);
+            shell.status(GOOD, "Unpacking", &archive_path);
             unpack_skeleton_archive(opts).context("unpacking skeleton archive")?;
-            println!("{GOOD}Finished{GOOD:#}");
+
+            shell.event(Event::ArchiveUnpacked {
+                path: &archive_path,
+                dest: &args.out_path,
+                duration_ms: start.elapsed().as_millis(),
+            });
+            shell.status(GOOD, "Finished", "");
         }
         SkeletonCommand::Build(args) => {
+            let manifest_path = manifest_path(&args.manifest)?;
+
+            // The build settings of an archive are read before it is
+            // unpacked, since the workspace doesn't exist yet.
+            let settings = match &args.archive {
+                Some(archive_path) => Settings::from_archive(archive_path)?,
+                None => Settings::load(&manifest_path)?,
+            }
+            .build;
+
             let opts = BuildOptions {
                 manifest_path: args
                     .manifest
                     .manifest_path
                     .map(|p| p.to_owned().try_into().unwrap()),
-                packages: args.workspace.package,
-                exclude: args.workspace.exclude,
+                packages: or_default(args.workspace.package, settings.packages),
+                exclude: or_default(args.workspace.exclude, settings.exclude),
                 all: args.workspace.all,
-                args: args.args,
+                // A cargo command given on the command line takes
+                // precedence over a configured subcommand.
+                subcommand: match args.cargo_command {
+                    Some(_) => args.subcommand,
+                    None => or_default(args.subcommand, settings.subcommand),
+                },
+                cargo_command: args
+                    .cargo_command
+                    .map(CargoCommand::from)
+                    .or(settings.cargo_command)
+                    .unwrap_or_default(),
+                wrapper: args.wrapper.or(settings.wrapper),
+                profiles: or_default(args.profiles, settings.profiles),
+                targets: or_default(args.targets, settings.targets),
+                force: args.force,
+                args: or_default(args.args, settings.args),
+                output_args: shell.cargo_args(),
             };
 
-            build_skeleton_package(opts).context("building skeleton packages")?;
+            if args.dry_run || args.print_plan {
+                let plan = plan_skeleton_build(&opts).context("planning skeleton build")?;
+
+                if args.print_plan {
+                    let json = serde_json::to_string_pretty(&plan).context("serializing plan")?;
+                    println!("{}", json);
+                } else if shell.is_json() {
+                    shell.event(Event::BuildPlan { plan: &plan });
+                } else {
+                    print!("{}", plan.to_shell());
+                }
+
+                return Ok(());
+            }
+
+            let report = match args.archive {
+                Some(archive_path) => {
+                    let dest_path = manifest_path
+                        .parent()
+                        .map(|path| path.to_owned())
+                        .unwrap_or_default();
+                    let unpack = UnpackOptions {
+                        archive_path: Some(archive_path.clone()),
+                        dest_path: Some(dest_path.clone()),
+                    };
+
+                    shell.status(GOOD, "Unpacking", &archive_path);
+                    let report = build_skeleton_archive(unpack, opts)
+                        .context("building skeleton archive")?;
+
+                    shell.event(Event::ArchiveUnpacked {
+                        path: &archive_path,
+                        dest: &dest_path,
+                        duration_ms: report.unpack_duration.unwrap_or_default().as_millis(),
+                    });
+
+                    report
+                }
+                None => build_skeleton_variants(opts).context("building skeleton packages")?,
+            };
+
+            for command in report.commands.iter() {
+                shell.event(Event::CommandFinished {
+                    package: &command.package,
+                    variant: &command.variant,
+                    command: &command.command,
+                    duration_ms: command.duration.as_millis(),
+                    success: command.success,
+                });
+            }
+
+            for variant in report.fresh.iter() {
+                shell.status(GOOD, "Fresh", variant);
+                shell.event(Event::VariantFinished { variant, status: "fresh", error: None });
+            }
+            for variant in report.built.iter() {
+                shell.status(GOOD, "Finished", variant);
+                shell.event(Event::VariantFinished { variant, status: "built", error: None });
+            }
+            for (variant, err) in report.failed.iter() {
+                shell.status(ERROR, "Failed", format!("{}: {:#}", variant, err));
+                shell.event(Event::VariantFinished {
+                    variant,
+                    status: "failed",
+                    error: Some(format!("{:#}", err)),
+                });
+            }
+
+            shell.event(Event::BuildFinished {
+                fresh: report.fresh.len(),
+                built: report.built.len(),
+                failed: report.failed.len(),
+                duration_ms: start.elapsed().as_millis(),
+            });
+
+            let total = report.failed.len() + report.built.len();
+            let failed = report.failed.len();
+
+            if let Some((_, err)) = report.failed.into_iter().next() {
+                return Err(anyhow::Error::from(err).context(format!(
+                    "Failed to build dependencies for {} of {} combinations",
+                    failed, total
+                )));
+            }
         },
+        SkeletonCommand::Clean(args) => {
+            let opts = CleanOptions {
+                manifest_path: args
+                    .manifest
+                    .manifest_path
+                    .map(|p| p.to_owned().try_into().unwrap()),
+                target_dir: args.target_dir,
+                dry_run: args.dry_run,
+            };
+            let dry_run = opts.dry_run;
+
+            let removed = clean_skeleton(opts).context("cleaning skeleton")?;
+
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            for path in removed.iter() {
+                shell.status(GOOD, verb, path);
+                shell.event(Event::FileRemoved { path, dry_run });
+            }
+
+            shell.event(Event::CleanFinished { removed: removed.len(), dry_run });
+            shell.status(GOOD, "Finished", format!("{} files", removed.len()));
+        }
+        SkeletonCommand::Audit(args) => {
+            let opts = AuditOptions {
+                manifest_path: args
+                    .manifest
+                    .manifest_path
+                    .map(|p| p.to_owned().try_into().unwrap()),
+                input: args.input,
+                args: args.args,
+            };
+
+            let report = audit_build(opts).context("auditing build")?;
+
+            for package in report.fresh.iter() {
+                shell.event(Event::DependencyAudited { package, fresh: true });
+            }
+            for package in report.rebuilt.iter() {
+                shell.status(WARN, "Rebuilt", package);
+                shell.event(Event::DependencyAudited { package, fresh: false });
+            }
+
+            shell.event(Event::AuditFinished {
+                fresh: report.fresh.len(),
+                rebuilt: report.rebuilt.len(),
+            });
+
+            if !report.rebuilt.is_empty() {
+                bail!(
+                    "{} of {} dependencies were recompiled",
+                    report.rebuilt.len(),
+                    report.rebuilt.len() + report.fresh.len()
+                );
+            }
+
+            shell.status(GOOD, "Finished", format!("{} dependencies were fresh", report.fresh.len()));
+        }
+        SkeletonCommand::VerifySources(args) => {
+            let opts = VerifySourcesOptions {
+                manifest_path: args
+                    .manifest
+                    .manifest_path
+                    .map(|p| p.to_owned().try_into().unwrap()),
+                check_artifacts: args.check_artifacts,
+                target_dir: args.target_dir,
+            };
+
+            let checked = verify_sources(opts).context("verifying sources")?;
+
+            shell.event(Event::SourcesVerified { targets: checked });
+            shell.status(GOOD, "Finished", format!("{} targets have real sources", checked));
+        }
+        SkeletonCommand::Dockerfile(args) => {
+            let metadata = args
+                .manifest
+                .metadata()
+                .no_deps()
+                .exec()
+                .context("executing cargo metadata")?;
+            let settings = Settings::from_metadata(&metadata)?;
+
+            let root = &metadata.workspace_root;
+            let out_path = match args.out_path {
+                Some(path) if path.is_relative() => {
+                    let cwd: Utf8PathBuf = env::current_dir()
+                        .context("getting current dir")?
+                        .try_into()
+                        .context("current dir should be utf-8")?;
+                    cwd.join(path)
+                }
+                Some(path) => path,
+                None => root.join("Dockerfile"),
+            };
+
+            let opts = DockerfileOptions {
+                packages: args.packages,
+                features: feature_opts(args.features, &settings),
+                profile: args.profile,
+                target: args.target,
+                archive_path: settings.out_path,
+                base_image: args.base_image,
+                runtime_image: Some(args.runtime_image),
+                skeleton_image: Some(args.skeleton_image),
+            };
+
+            let files =
+                generate_dockerfile(&metadata, &opts, &out_path).context("generating Dockerfile")?;
+            let outdated = write_docker_files(&files, root, &out_path, args.check)
+                .context("writing Dockerfile")?;
+
+            for path in [out_path.clone(), dockerignore_path(root, &out_path)] {
+                let fresh = !outdated.contains(&path);
+                shell.event(Event::FileGenerated {
+                    path: &path,
+                    fresh,
+                    check: args.check,
+                });
+
+                match (fresh, args.check) {
+                    (true, _) => shell.status(GOOD, "Fresh", &path),
+                    (false, true) => shell.status(ERROR, "Outdated", &path),
+                    (false, false) => shell.status(GOOD, "Writing", &path),
+                }
+            }
+
+            if args.check && !outdated.is_empty() {
+                bail!("Generated files are out of date, run `cargo skeleton dockerfile` to update them");
+            }
+        }
+        SkeletonCommand::Completions(args) => {
+            let completion_shell = args
+                .shell
+                .or_else(clap_complete::Shell::from_env)
+                .context("no shell given and $SHELL is not a known shell")?;
+
+            write_registration(completion_shell, &mut io::stdout())?;
+        }
         SkeletonCommand::Mangen(args) => {
-            clap_mangen::generate_to(Cli::command(), args.out_path)
+            clap_mangen::generate_to(command(), args.out_path)
                 .context("generating man pages")?;
         },
     }
@@ -172,14 +1149,191 @@ pub fn run(cli: Cli) -> Result<()> {
     Ok(())
 }
 
+/// Returns the path to the manifest given with `--manifest-path`, or
+/// the manifest in the current directory.
+fn manifest_path(manifest: &clap_cargo::Manifest) -> Result<Utf8PathBuf> {
+    let path = match &manifest.manifest_path {
+        Some(path) => path.to_owned(),
+        None => env::current_dir()
+            .context("getting current dir")?
+            .join("Cargo.toml"),
+    };
+
+    path.try_into().context("manifest path should be utf-8")
+}
+
+/// Returns the create options from the command line, falling back to
+/// the configured defaults.
+fn create_options(args: CreateArgs, settings: Settings) -> CreateOptions {
+    let out_path = args
+        .out_path
+        .or(settings.out_path.clone())
+        .unwrap_or_else(|| DEFAULT_OUT_PATH.into());
+
+    CreateOptions {
+        out_path: Some(out_path),
+        scrub_secrets: flag(args.scrub_secrets, args.no_scrub_secrets, settings.scrub_secrets),
+        features: feature_opts(args.features, &settings),
+        packages: or_default(args.packages, settings.packages),
+        exclude: or_default(args.exclude, settings.exclude),
+        keep_source: or_default(args.keep_source, settings.keep_source),
+        include_files: or_default(args.include_files, settings.include_files),
+        exclude_files: or_default(args.exclude_files, settings.exclude_files),
+        verify: flag(args.verify, args.no_verify, settings.verify),
+    }
+}
+
+/// Returns the feature flags given on the command line, or the
+/// configured defaults if no feature flags were given. Feature flags
+/// on the command line replace all of the configured ones.
+fn feature_opts(args: clap_cargo::Features, settings: &Settings) -> Vec<CargoOpt> {
+    let (features, all_features, no_default_features) =
+        if args.features.is_empty() && !args.all_features && !args.no_default_features {
+            (
+                settings.features.clone(),
+                settings.all_features,
+                settings.no_default_features,
+            )
+        } else {
+            (args.features, args.all_features, args.no_default_features)
+        };
+
+    let mut opts = vec![];
+    if all_features {
+        opts.push(CargoOpt::AllFeatures);
+    }
+    if no_default_features {
+        opts.push(CargoOpt::NoDefaultFeatures);
+    }
+    if !features.is_empty() {
+        opts.push(CargoOpt::SomeFeatures(features));
+    }
+
+    opts
+}
+
+/// Returns true if the flag was given, false if its negation was, or
+/// the configured default otherwise.
+fn flag(yes: bool, no: bool, default: bool) -> bool {
+    match (yes, no) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => default,
+    }
+}
+
+/// Returns the command line values, or the configured defaults if
+/// none were given.
+fn or_default(args: Vec<String>, defaults: Vec<String>) -> Vec<String> {
+    if args.is_empty() {
+        defaults
+    } else {
+        args
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn verify_cli() {
-        use clap::CommandFactory;
+        command().debug_assert();
+    }
+
+    fn create_args(args: &[&str]) -> CreateArgs {
+        let matches = command()
+            .try_get_matches_from(["cargo", "skeleton", "create"].iter().chain(args))
+            .unwrap();
+
+        match Cli::from_arg_matches(&matches).unwrap() {
+            Cli::Skeleton(SkeletonArgs {
+                command: SkeletonCommand::Create(args),
+                ..
+            }) => args,
+            cli => panic!("unexpected command: {:?}", cli),
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            features: vec!["tls".into()],
+            all_features: true,
+            scrub_secrets: true,
+            verify: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn create_uses_configured_flags() {
+        let opts = create_options(create_args(&[]), settings());
+
+        assert!(opts.scrub_secrets);
+        assert!(opts.verify);
+        assert_eq!(
+            "[AllFeatures, SomeFeatures([\"tls\"])]",
+            format!("{:?}", opts.features)
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_configured_flags() {
+        let args = create_args(&["--no-scrub-secrets", "--no-verify", "--no-default-features"]);
+        let opts = create_options(args, settings());
+
+        assert!(!opts.scrub_secrets);
+        assert!(!opts.verify);
+        assert_eq!("[NoDefaultFeatures]", format!("{:?}", opts.features));
+
+        let args = create_args(&["--no-verify", "--verify"]);
+
+        assert!(create_options(args, Settings::default()).verify);
+    }
+
+    fn build_args(args: &[&str]) -> Result<BuildArgs, clap::Error> {
+        let matches =
+            command().try_get_matches_from(["cargo", "skeleton", "build"].iter().chain(args))?;
+
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        resolve_conflicts(&mut cli, &matches)?;
+
+        match cli {
+            Cli::Skeleton(SkeletonArgs {
+                command: SkeletonCommand::Build(args),
+                ..
+            }) => Ok(args),
+            cli => panic!("unexpected command: {:?}", cli),
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_conflicting_env() {
+        // Both pairs are checked in one test, since the environment
+        // is shared between tests.
+        env::set_var("CARGO_SKELETON_CARGO_COMMAND", "check");
+        let args = build_args(&["--subcommand", "zigbuild"]);
+        env::remove_var("CARGO_SKELETON_CARGO_COMMAND");
+
+        let args = args.unwrap();
+        assert_eq!(None, args.cargo_command);
+        assert_eq!(vec!["zigbuild"], args.subcommand);
+
+        env::set_var("CARGO_SKELETON_BUILD_ARCHIVE", "skeleton.tar");
+        let args = build_args(&["--dry-run"]);
+        env::remove_var("CARGO_SKELETON_BUILD_ARCHIVE");
+
+        let args = args.unwrap();
+        assert_eq!(None, args.archive);
+        assert!(args.dry_run);
+    }
+
+    #[test]
+    fn conflicting_command_line_args_fail() {
+        let err = build_args(&["--cargo-command", "check", "--subcommand", "zigbuild"]).unwrap_err();
+        assert_eq!(ErrorKind::ArgumentConflict, err.kind());
 
-        Cli::command().debug_assert();
+        let err = build_args(&["--archive", "skeleton.tar", "--dry-run"]).unwrap_err();
+        assert_eq!(ErrorKind::ArgumentConflict, err.kind());
     }
 }
@@ -0,0 +1,96 @@
+use std::{env, ffi::OsStr, io::Write};
+
+use anyhow::{Context, Result};
+use cargo_metadata::MetadataCommand;
+use clap_complete::{env::Shells, CompletionCandidate, Shell};
+
+use crate::lockfile::{load_lockfile, LOCKFILE_NAME};
+
+/// The environment variable that asks the binary for completions
+/// instead of running a command.
+pub const COMPLETE_VAR: &str = "CARGO_SKELETON_COMPLETE";
+
+/// The binary name completions are registered for. Registering them for
+/// `cargo` would replace the completions of every other Cargo command.
+const COMPLETE_BIN: &str = "cargo-skeleton";
+
+/// Write the script registering completions for the `cargo-skeleton`
+/// binary in a shell.
+///
+/// The script calls back into the current executable with
+/// [`COMPLETE_VAR`] set for each completion, so it should be
+/// regenerated after upgrading.
+pub fn write_registration(shell: Shell, out: &mut dyn Write) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .with_context(|| format!("{} completions are not supported", shell))?;
+
+    let exe = env::current_exe().context("getting current executable")?;
+
+    completer
+        .write_registration(
+            COMPLETE_VAR,
+            COMPLETE_BIN,
+            COMPLETE_BIN,
+            &exe.to_string_lossy(),
+            out,
+        )
+        .context("writing completion script")
+}
+
+/// Complete the name of a workspace package.
+///
+/// In a skeleton the names are read from `Skeleton.lock`, otherwise
+/// the workspace members are listed with `cargo metadata`.
+pub fn complete_packages(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return vec![];
+    };
+
+    let mut names = package_names().unwrap_or_default();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(current))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+fn package_names() -> Result<Vec<String>> {
+    let metadata = MetadataCommand::new()
+        .no_deps()
+        .exec()
+        .context("executing `cargo metadata`")?;
+
+    let root = &metadata.workspace_root;
+
+    if root.join(LOCKFILE_NAME).exists() {
+        let lockfile = load_lockfile(root)?;
+        return Ok(lockfile.packages.into_iter().map(|pkg| pkg.name).collect());
+    }
+
+    Ok(metadata
+        .workspace_packages()
+        .into_iter()
+        .map(|pkg| pkg.name.clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_binary_completions() {
+        let mut out = vec![];
+        write_registration(Shell::Bash, &mut out).unwrap();
+
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("CARGO_SKELETON_COMPLETE=\"bash\""));
+        assert!(script.contains("-F _clap_complete_cargo_skeleton cargo-skeleton"));
+        assert!(!script.contains(" cargo\n"));
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use log::*;
+
+/// File names Cargo reads configuration from, relative to a directory.
+const CARGO_CONFIG_NAMES: &[&str] = &[".cargo/config", ".cargo/config.toml"];
+
+/// File names rustup reads the toolchain from, relative to a directory.
+pub(crate) const TOOLCHAIN_NAMES: &[&str] = &["rust-toolchain", "rust-toolchain.toml"];
+
+/// An additional file that affects compilation, such as a Cargo
+/// config file or a toolchain file.
+#[derive(Debug)]
+pub struct ConfigFile {
+    /// Path of the file, relative to the workspace root
+    pub path: Utf8PathBuf,
+
+    /// The parsed contents if the file is a Cargo config file
+    pub table: Option<toml::Table>,
+}
+
+/// Discover every config file Cargo would read when invoked from the
+/// workspace root or from any of the given member directories.
+///
+/// Cargo searches the current directory and each of its parents, so
+/// config files are collected from every directory between a member
+/// and the workspace root. Files referenced with `include` are
+/// followed recursively. Files outside of the workspace root cannot
+/// be added to a skeleton and are skipped with a warning.
+pub fn discover_config_files<'a>(
+    root: &Utf8Path,
+    member_dirs: impl IntoIterator<Item = &'a Utf8Path>,
+) -> Result<Vec<ConfigFile>> {
+    let mut dirs: Vec<&Utf8Path> = vec![root];
+
+    for dir in member_dirs {
+        dirs.extend(dir.ancestors().take_while(|dir| dir.starts_with(root)));
+    }
+
+    dirs.sort();
+    dirs.dedup();
+
+    let mut files = BTreeMap::new();
+
+    for dir in dirs {
+        for name in TOOLCHAIN_NAMES {
+            let path = dir.join(name);
+            if path.is_file() {
+                files.insert(relative_path(root, &path), None);
+            }
+        }
+
+        for name in CARGO_CONFIG_NAMES {
+            let path = dir.join(name);
+            if path.is_file() {
+                load_cargo_config(root, &path, &mut files)?;
+            }
+        }
+    }
+
+    for dir in root.ancestors().skip(1) {
+        for name in CARGO_CONFIG_NAMES {
+            let path = dir.join(name);
+            if path.is_file() {
+                warn!(
+                    "Config file {} is outside of the workspace root and will not be added to the skeleton",
+                    path
+                );
+            }
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|(path, table)| ConfigFile { path, table })
+        .collect())
+}
+
+/// Find local files referenced by Cargo config files.
+///
+/// Cargo config may point at files that change how dependencies
+/// are compiled: `[env]` entries with `relative = true`, linker
+/// and runner wrappers, linker scripts passed in `rustflags`, and
+/// search paths in `[target.<triple>.<links>]` build script
+/// overrides. Every referenced regular file inside the workspace
+/// root is returned, relative to the root.
+///
+/// Referenced directories are not expanded, since they are often
+/// the workspace root itself, e.g. an `[env]` entry with an empty
+/// relative path. Files in them can be added with include globs.
+pub fn find_referenced_files(root: &Utf8Path, configs: &[ConfigFile]) -> Vec<Utf8PathBuf> {
+    let mut files = BTreeSet::new();
+
+    for config in configs.iter() {
+        let table = match &config.table {
+            Some(table) => table,
+            None => continue,
+        };
+
+        let config_root = config_root(root, config);
+
+        for reference in config_references(table) {
+            let candidates = match reference {
+                Reference::ConfigRelative(path) => vec![config_root.join(path)],
+                Reference::Program(path) if path.contains(['/', '\\']) => {
+                    vec![config_root.join(path)]
+                }
+                Reference::Program(_) => continue,
+                Reference::Flag(path) => vec![root.join(&path), config_root.join(&path)],
+            };
+
+            for path in candidates.iter().map(|path| normalize_path(path)) {
+                if !path.starts_with(root) {
+                    debug!("Skipping referenced path outside of workspace: {}", path);
+                    continue;
+                }
+
+                if path == root {
+                    debug!("Skipping workspace root referenced by {}", config.path);
+                } else if path.is_dir() {
+                    warn!(
+                        "Directory {} referenced by {} will not be added to the skeleton, add its files with an include glob",
+                        path, config.path
+                    );
+                } else if path.is_file() {
+                    debug!("Found file referenced by {}: {}", config.path, path);
+                    files.insert(relative_path(root, &path));
+                }
+            }
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+/// Find local packages referenced by `[patch]` and `[replace]`
+/// entries in the root manifest or in Cargo config files.
+///
+/// Cargo loads every patch while resolving, even one that no
+/// dependency uses, so their manifests have to be in a skeleton.
+/// Returns the package directories inside the workspace root,
+/// relative to the root.
+pub fn find_referenced_packages(
+    root: &Utf8Path,
+    configs: &[ConfigFile],
+) -> Result<Vec<Utf8PathBuf>> {
+    let contents = fs::read_to_string(root.join("Cargo.toml")).context("reading root manifest")?;
+    let manifest: toml::Table = toml::from_str(&contents).context("parsing root manifest")?;
+
+    let sources = configs
+        .iter()
+        .filter_map(|config| Some((config_root(root, config), config.table.as_ref()?)))
+        .chain(Some((root.to_owned(), &manifest)));
+
+    let mut dirs = BTreeSet::new();
+
+    for (base, table) in sources {
+        for path in patch_paths(table) {
+            let dir = normalize_path(&base.join(path));
+
+            if !dir.starts_with(root) {
+                debug!("Skipping patched package outside of workspace: {}", dir);
+                continue;
+            }
+
+            if dir.join("Cargo.toml").is_file() {
+                dirs.insert(relative_path(root, &dir));
+            }
+        }
+    }
+
+    Ok(dirs.into_iter().collect())
+}
+
+/// Returns the paths of `[patch.<source>]` and `[replace]` entries.
+fn patch_paths(table: &toml::Table) -> Vec<&str> {
+    let patches = table
+        .get("patch")
+        .and_then(|patch| patch.as_table())
+        .into_iter()
+        .flat_map(|patch| patch.values())
+        .filter_map(|source| source.as_table())
+        .chain(table.get("replace").and_then(|replace| replace.as_table()));
+
+    patches
+        .flat_map(|deps| deps.values())
+        .filter_map(|dep| dep.get("path").and_then(|path| path.as_str()))
+        .collect()
+}
+
+/// Returns the directory relative paths in a config file are
+/// resolved against, i.e. the directory containing `.cargo`.
+fn config_root(root: &Utf8Path, config: &ConfigFile) -> Utf8PathBuf {
+    root.join(&config.path)
+        .parent()
+        .and_then(|dir| dir.parent())
+        .map(|dir| dir.to_owned())
+        .unwrap_or_else(|| root.to_owned())
+}
+
+/// A path referenced from a config file.
+#[derive(Debug, PartialEq)]
+enum Reference {
+    /// A path relative to the config root
+    ConfigRelative(String),
+    /// A program, which is a path if it contains a slash
+    Program(String),
+    /// A path passed to the compiler or linker
+    Flag(String),
+}
+
+fn config_references(table: &toml::Table) -> Vec<Reference> {
+    let mut refs = vec![];
+
+    if let Some(env) = table.get("env").and_then(|env| env.as_table()) {
+        for value in env.values() {
+            let is_relative = value
+                .get("relative")
+                .and_then(|relative| relative.as_bool())
+                .unwrap_or(false);
+
+            if let Some(path) = value.get("value").and_then(|path| path.as_str()) {
+                if is_relative {
+                    refs.push(Reference::ConfigRelative(path.to_string()));
+                }
+            }
+        }
+    }
+
+    if let Some(build) = table.get("build").and_then(|build| build.as_table()) {
+        for key in ["rustc", "rustc-wrapper", "rustc-workspace-wrapper"] {
+            if let Some(program) = build.get(key).and_then(|program| program.as_str()) {
+                refs.push(Reference::Program(program.to_string()));
+            }
+        }
+
+        refs.extend(rustflags_references(build.get("rustflags")));
+    }
+
+    let targets = table
+        .get("target")
+        .and_then(|target| target.as_table())
+        .into_iter()
+        .flat_map(|target| target.values())
+        .chain(table.get("host"))
+        .filter_map(|target| target.as_table());
+
+    for target in targets {
+        for (key, value) in target.iter() {
+            match (key.as_str(), value) {
+                ("linker", toml::Value::String(program)) => {
+                    refs.push(Reference::Program(program.clone()));
+                }
+                ("runner", value) => {
+                    let program = match value {
+                        toml::Value::Array(args) => args.first().and_then(|arg| arg.as_str()),
+                        value => value.as_str().and_then(|s| s.split_whitespace().next()),
+                    };
+                    if let Some(program) = program {
+                        refs.push(Reference::Program(program.to_string()));
+                    }
+                }
+                ("rustflags", value) => refs.extend(rustflags_references(Some(value))),
+                // Any other table is a build script override for a
+                // package with a `links` key.
+                (_, toml::Value::Table(overrides)) => {
+                    let search_paths = overrides
+                        .get("rustc-link-search")
+                        .and_then(|paths| paths.as_array())
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|path| path.as_str());
+
+                    for path in search_paths {
+                        let path = path.split_once('=').map_or(path, |(_, path)| path);
+                        refs.push(Reference::ConfigRelative(path.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    refs
+}
+
+/// Find linker scripts and other linker inputs in a `rustflags`
+/// value, e.g. `-C link-arg=-Tlink.x`.
+fn rustflags_references(value: Option<&toml::Value>) -> Vec<Reference> {
+    let flags: Vec<String> = match value {
+        Some(toml::Value::String(flags)) => flags
+            .split_whitespace()
+            .map(|flag| flag.to_string())
+            .collect(),
+        Some(toml::Value::Array(flags)) => flags
+            .iter()
+            .filter_map(|flag| flag.as_str())
+            .map(|flag| flag.to_string())
+            .collect(),
+        _ => return vec![],
+    };
+
+    let mut refs = vec![];
+    let mut flags = flags.iter().peekable();
+
+    while let Some(flag) = flags.next() {
+        let codegen = match flag.strip_prefix("-C") {
+            Some("") => flags.next().map(|flag| flag.as_str()),
+            Some(codegen) => Some(codegen),
+            None => None,
+        };
+
+        let args = match codegen.and_then(|codegen| codegen.split_once('=')) {
+            Some(("link-arg", arg)) => vec![arg],
+            Some(("link-args", args)) => args.split(' ').collect(),
+            _ => continue,
+        };
+
+        for arg in args {
+            let arg = arg.strip_prefix("-Wl,").unwrap_or(arg);
+            let path = arg
+                .strip_prefix("-T")
+                .map(|path| path.trim_start_matches(','))
+                .unwrap_or(arg);
+
+            if !path.is_empty() && !path.starts_with('-') {
+                refs.push(Reference::Flag(path.to_string()));
+            }
+        }
+    }
+
+    refs
+}
+
+/// Load a Cargo config file and any files it includes.
+fn load_cargo_config(
+    root: &Utf8Path,
+    path: &Utf8Path,
+    files: &mut BTreeMap<Utf8PathBuf, Option<toml::Table>>,
+) -> Result<()> {
+    let rel_path = relative_path(root, path);
+
+    if files.contains_key(&rel_path) {
+        return Ok(());
+    }
+
+    debug!("Found config file: {}", rel_path);
+
+    let contents = fs::read_to_string(path).context(format!("reading config file: {}", path))?;
+    let table: toml::Table =
+        toml::from_str(&contents).context(format!("parsing config file: {}", path))?;
+
+    let includes = config_includes(&table);
+
+    files.insert(rel_path, Some(table));
+
+    let base = path.parent().expect("config file has a parent directory");
+
+    for include in includes {
+        let include_path = normalize_path(&base.join(&include));
+
+        if !include_path.starts_with(root) {
+            warn!(
+                "Config file {} included from {} is outside of the workspace root and will not be added to the skeleton",
+                include_path, path
+            );
+            continue;
+        }
+
+        if !include_path.is_file() {
+            debug!("Skipping missing config include: {}", include_path);
+            continue;
+        }
+
+        load_cargo_config(root, &include_path, files)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the paths listed in a config file's `include` key.
+///
+/// The key may be a single path, an array of paths, or an array of
+/// tables with a `path` key.
+fn config_includes(table: &toml::Table) -> Vec<String> {
+    let include = match table.get("include") {
+        Some(include) => include,
+        None => return vec![],
+    };
+
+    let values = match include {
+        toml::Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+
+    values
+        .into_iter()
+        .filter_map(|value| match value {
+            toml::Value::String(path) => Some(path.clone()),
+            toml::Value::Table(table) => table
+                .get("path")
+                .and_then(|path| path.as_str())
+                .map(|path| path.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the path relative to the root, or the path itself if it
+/// is not within the root.
+pub(crate) fn relative_path(root: &Utf8Path, path: &Utf8Path) -> Utf8PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_owned()
+}
+
+/// Lexically resolve `.` and `..` components without touching the
+/// filesystem.
+pub(crate) fn normalize_path(path: &Utf8Path) -> Utf8PathBuf {
+    use camino::Utf8Component;
+
+    let mut out = Utf8PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn discovers_member_configs_and_includes() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, ".cargo/config.toml", "include = \"shared.toml\"\n");
+        write(root, ".cargo/shared.toml", "[build]\njobs = 1\n");
+        write(
+            root,
+            "rust-toolchain.toml",
+            "[toolchain]\nchannel = \"stable\"\n",
+        );
+        write(
+            root,
+            "crates/foo/.cargo/config.toml",
+            "[env]\nFOO = \"1\"\n",
+        );
+        write(
+            root,
+            "crates/bar/.cargo/config.toml",
+            "[env]\nBAR = \"1\"\n",
+        );
+
+        let member = root.join("crates/foo");
+        let files = discover_config_files(root, [member.as_path()]).unwrap();
+
+        let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+
+        assert_eq!(
+            vec![
+                ".cargo/config.toml",
+                ".cargo/shared.toml",
+                "crates/foo/.cargo/config.toml",
+                "rust-toolchain.toml",
+            ],
+            paths
+        );
+        assert!(files[0].table.is_some());
+        assert!(files[3].table.is_none());
+    }
+
+    #[test]
+    fn finds_referenced_files() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            ".cargo/config.toml",
+            r#"
+            [env]
+            OPENSSL_CONF = { value = "openssl.cnf", relative = true }
+            NOT_A_PATH = "missing.txt"
+
+            [target.x86_64-unknown-linux-gnu]
+            linker = "tools/linker.sh"
+            runner = ["qemu-x86_64", "-L", "/usr"]
+            rustflags = ["-C", "link-arg=-Tmemory.x", "-Clink-arg=-Wl,-T,link.ld"]
+
+            [target.x86_64-unknown-linux-gnu.foo]
+            rustc-link-search = ["native=vendor/foo"]
+            rustc-link-lib = ["foo"]
+            "#,
+        );
+        write(root, "openssl.cnf", "");
+        write(root, "missing.txt", "");
+        write(root, "tools/linker.sh", "");
+        write(root, "memory.x", "");
+        write(root, "link.ld", "");
+        write(root, "vendor/foo/libfoo.a", "");
+
+        let configs = discover_config_files(root, []).unwrap();
+        let files = find_referenced_files(root, &configs);
+
+        let paths: Vec<&str> = files.iter().map(|file| file.as_str()).collect();
+
+        assert_eq!(
+            vec!["link.ld", "memory.x", "openssl.cnf", "tools/linker.sh",],
+            paths
+        );
+    }
+
+    #[test]
+    fn skips_referenced_directories() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            ".cargo/config.toml",
+            r#"
+            [env]
+            CARGO_WORKSPACE_DIR = { value = "", relative = true }
+            ASSETS = { value = "assets", relative = true }
+            "#,
+        );
+        write(root, "assets/logo.png", "");
+        write(root, "src/main.rs", "fn main() {}");
+        write(root, "target/debug/app", "");
+
+        let configs = discover_config_files(root, []).unwrap();
+
+        assert!(find_referenced_files(root, &configs).is_empty());
+    }
+
+    #[test]
+    fn finds_patched_packages() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            r#"
+            [workspace]
+
+            [patch.crates-io]
+            foo = { path = "vendor/foo" }
+            serde = { git = "https://github.com/serde-rs/serde" }
+
+            [replace]
+            "bar:0.1.0" = { path = "vendor/bar" }
+            "#,
+        );
+        write(
+            root,
+            ".cargo/config.toml",
+            "[patch.crates-io]\nbaz = { path = \"vendor/baz\" }\nqux = { path = \"../qux\" }\n",
+        );
+
+        for name in ["foo", "bar", "baz"] {
+            write(root, &format!("vendor/{}/Cargo.toml", name), "");
+        }
+
+        let configs = discover_config_files(root, []).unwrap();
+        let dirs = find_referenced_packages(root, &configs).unwrap();
+
+        let paths: Vec<&str> = dirs.iter().map(|dir| dir.as_str()).collect();
+
+        assert_eq!(vec!["vendor/bar", "vendor/baz", "vendor/foo"], paths);
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            Utf8PathBuf::from("/foo/baz"),
+            normalize_path(Utf8Path::new("/foo/./bar/../baz"))
+        );
+    }
+}
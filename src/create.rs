@@ -1,10 +1,29 @@
-use crate::{lockfile::LOCKFILE_NAME, workspace::Workspace};
-use anyhow::{Context, Result};
-use camino::Utf8PathBuf;
-use cargo_metadata::{Metadata, Target};
+use crate::{
+    cargo_lock::{CargoLock, CARGO_LOCK_NAME},
+    config::{
+        discover_config_files, find_referenced_files, find_referenced_packages, relative_path,
+    },
+    error::SkeletonError,
+    lockfile::{Lockfile, LOCKFILE_NAME},
+    package::PackageId,
+    secrets::{find_secrets, is_credentials_file, scrub_secrets},
+    verify::{verify_skeleton_archive, VCS_DIRS},
+    workspace::Workspace,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{CargoOpt, Metadata, Target};
+use glob::{MatchOptions, Pattern};
 use itertools::Itertools;
 use log::*;
-use std::{env, fs::File};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    process::Command,
+};
 
 pub const DEFAULT_OUT_PATH: &str = "skeleton.tar";
 
@@ -24,83 +43,1012 @@ fn main() {}
 
 "#;
 
-/// Paths for additional config files that affect compilation.
-const CONFIG_PATHS: &[&str] = &[
-    ".cargo/config",
-    ".cargo/config.toml",
-    "rust-toolchain",
-    "rust-toolchain.toml",
-];
+/// Options for [`create_skeleton`]. The [`SkeletonBuilder`] sets the
+/// same options, except for the output path.
+///
+/// Fields may be added in future releases, so the options are created
+/// with [`CreateOptions::new`] or `Default` and then changed.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct CreateOptions {
+    /// Path to write the skeleton archive to
+    pub out_path: Option<Utf8PathBuf>,
 
-pub fn create_skeleton(metadata: Metadata, out_path: Utf8PathBuf) -> Result<()> {
-    info!("Using workspace root: {}", metadata.workspace_root);
+    /// Remove secrets from config files instead of failing
+    pub scrub_secrets: bool,
 
-    info!("Writing to {}", out_path);
+    /// Only include these packages and the members they depend on
+    pub packages: Vec<String>,
 
-    let root = &metadata.workspace_root;
+    /// Leave these packages out unless a selected package depends on them
+    pub exclude: Vec<String>,
 
-    env::set_current_dir(root).context("changing current directory to workspace root")?;
+    /// Add the complete source of these packages instead of stubs
+    pub keep_source: Vec<String>,
 
-    let file = File::create(out_path).context("opening out file")?;
+    /// Globs matching additional files to add as-is
+    pub include_files: Vec<String>,
 
-    let mut ar = tar::Builder::new(file);
+    /// Globs matching files to leave out of the archive
+    pub exclude_files: Vec<String>,
 
-    if metadata.root_package().is_none() {
-        ar.append_path("Cargo.toml")
-            .context("adding root manifest to archive")?;
+    /// Feature flags the metadata was resolved with
+    pub features: Vec<CargoOpt>,
+
+    /// Unpack the written archive and check that it resolves offline
+    pub verify: bool,
+}
+
+impl CreateOptions {
+    /// Create options writing the archive to the given path, relative
+    /// to the workspace root.
+    pub fn new(out_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            out_path: Some(out_path.into()),
+            ..Default::default()
+        }
     }
+}
+
+/// Details about a written skeleton archive.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct CreateReport {
+    /// Names of the packages in the skeleton
+    pub packages: Vec<String>,
 
-    ar.append_path("Cargo.lock")?;
+    /// Paths of the archive entries, in archive order
+    pub entries: Vec<Utf8PathBuf>,
 
-    for path in CONFIG_PATHS.iter() {
-        if root.join(path).exists() {
-            ar.append_path(path)
-                .context(format!("adding config file to archive: {}", path))?;
+    /// Size of the archive in bytes
+    pub size: u64,
+
+    /// SHA-256 digest of the archive, hex encoded
+    pub digest: String,
+}
+
+/// Create a skeleton archive at `opts.out_path`, relative to the
+/// workspace root.
+pub fn create_skeleton(metadata: Metadata, opts: CreateOptions) -> Result<CreateReport, SkeletonError> {
+    let out_path = metadata
+        .workspace_root
+        .join(opts.out_path.as_deref().unwrap_or(DEFAULT_OUT_PATH.as_ref()));
+
+    SkeletonBuilder { metadata, opts }.write_to_path(out_path)
+}
+
+/// Creates skeleton archives of a workspace.
+///
+/// The workspace is described by its Cargo metadata, which has to
+/// include the dependency graph, i.e. it must not be created with
+/// `no_deps`. Files are read relative to the workspace root, so the
+/// current directory does not matter.
+///
+/// ```no_run
+/// use cargo_skeleton::SkeletonBuilder;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let metadata = cargo_metadata::MetadataCommand::new().exec()?;
+///
+/// let mut archive = vec![];
+/// let report = SkeletonBuilder::new(metadata)
+///     .packages(["server"])
+///     .write(&mut archive)?;
+///
+/// println!("{} entries, sha256 {}", report.entries.len(), report.digest);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SkeletonBuilder {
+    metadata: Metadata,
+    opts: CreateOptions,
+}
+
+impl SkeletonBuilder {
+    /// Create a builder for the workspace described by the metadata.
+    pub fn new(metadata: Metadata) -> Self {
+        Self {
+            metadata,
+            opts: CreateOptions::default(),
         }
     }
 
-    let packages = metadata
-        .packages
-        .iter()
-        .filter(|pkg| pkg.source == None && pkg.manifest_path.starts_with(root))
-        .sorted_by(|a, b| Ord::cmp(&a.name, &b.name));
+    /// Only include these packages and the members they depend on.
+    pub fn packages(mut self, specs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.opts.packages = specs.into_iter().map(Into::into).collect();
+        self
+    }
 
-    for package in packages {
-        let path = package.manifest_path.strip_prefix(root).unwrap();
-        ar.append_path(path)
-            .context(format!("adding package manifest to archive: {}", path))?;
+    /// Leave these packages out unless a selected package depends on
+    /// them.
+    pub fn exclude(mut self, specs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.opts.exclude = specs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add the complete source of these packages instead of stubs.
+    pub fn keep_source(mut self, specs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.opts.keep_source = specs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add the files matching these globs, relative to the workspace
+    /// root, as-is.
+    pub fn include_files(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.opts.include_files = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Leave the files matching these globs out of the archive.
+    pub fn exclude_files(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.opts.exclude_files = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Remove secrets from config files instead of failing.
+    pub fn scrub_secrets(mut self, scrub: bool) -> Self {
+        self.opts.scrub_secrets = scrub;
+        self
+    }
+
+    /// The feature flags the metadata was resolved with, used to
+    /// resolve the skeleton when verifying it.
+    pub fn features(mut self, features: impl IntoIterator<Item = CargoOpt>) -> Self {
+        self.opts.features = features.into_iter().collect();
+        self
+    }
+
+    /// Check that the written archive resolves offline to the same
+    /// dependencies as the workspace.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.opts.verify = verify;
+        self
+    }
+
+    /// Write the skeleton archive to a sink.
+    ///
+    /// When verifying, the archive is buffered in memory and only
+    /// written once it passed verification.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<CreateReport, SkeletonError> {
+        if !self.opts.verify {
+            let (_, _, report) = self.write_archive(writer, None)?;
+            return Ok(report);
+        }
+
+        let (buf, lockfile, report) = self.write_archive(vec![], None)?;
+        self.verify_archive(&buf[..], &lockfile)?;
+
+        writer.write_all(&buf).context("writing archive")?;
+
+        Ok(report)
+    }
+
+    /// Write the skeleton archive to a file, replacing it if it exists.
+    ///
+    /// The archive is written to a temporary file next to `path` and
+    /// only moved into place once it is complete and verified, so a
+    /// failed run leaves any previous archive untouched.
+    pub fn write_to_path(&self, path: impl AsRef<Utf8Path>) -> Result<CreateReport, SkeletonError> {
+        let path = path.as_ref();
+
+        info!("Writing to {}", path);
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_str().is_empty() => dir,
+            _ => Utf8Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".cargo-skeleton");
+
+        // Temp files are private to the user, the archive is not.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            builder.permissions(fs::Permissions::from_mode(0o644));
+        }
 
-        let targets = package
-            .targets
+        let tmp_file = builder.tempfile_in(dir).context("creating temp file")?;
+
+        let (writer, lockfile, report) =
+            self.write_archive(BufWriter::new(tmp_file.as_file()), Some(path))?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .context("writing archive")?;
+
+        if self.opts.verify {
+            let file = File::open(tmp_file.path()).context("opening archive file")?;
+            self.verify_archive(file, &lockfile)?;
+        }
+
+        tmp_file
+            .persist(path)
+            .map_err(|err| err.error)
+            .context(format!("moving archive into place: {}", path))?;
+
+        Ok(report)
+    }
+
+    fn verify_archive(&self, archive: impl Read, lockfile: &Lockfile) -> Result<()> {
+        verify_skeleton_archive(archive, &self.metadata, lockfile, &self.opts.features)
+            .context("verifying skeleton archive")
+    }
+
+    /// Write the archive, returning the sink and the lockfile that was
+    /// added to it. The output path, if any, is never added.
+    fn write_archive<W: Write>(
+        &self,
+        writer: W,
+        out_path: Option<&Utf8Path>,
+    ) -> Result<(W, Lockfile, CreateReport)> {
+        let metadata = &self.metadata;
+        let opts = &self.opts;
+
+        info!("Using workspace root: {}", metadata.workspace_root);
+
+        let root = &metadata.workspace_root;
+
+        let mut ar = ArchiveWriter::new(root, writer);
+
+        let mut workspace = Workspace::new(root.clone());
+        workspace.load_metadata(metadata)?;
+
+        let selected = if opts.packages.is_empty() && opts.exclude.is_empty() {
+            None
+        } else {
+            let exclude = workspace.get_package_ids(&opts.exclude[..])?;
+            let packages: Vec<PackageId> = if opts.packages.is_empty() {
+                metadata
+                    .workspace_members
+                    .iter()
+                    .map(|id| id.clone().into())
+                    .collect()
+            } else {
+                workspace
+                    .get_package_ids(&opts.packages[..])?
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            };
+
+            let roots = packages.into_iter().filter(|id| !exclude.contains(&id));
+            let ids = member_closure(metadata, &workspace, roots)?;
+
+            for id in exclude.iter().filter(|id| ids.contains(*id)) {
+                info!("Including excluded package {} required by a selected package", id);
+            }
+
+            workspace.retain(|pkg| ids.contains(&pkg.id));
+            Some(ids)
+        };
+
+        let packages: Vec<_> = metadata
+            .packages
+            .iter()
+            .filter(|pkg| pkg.source.is_none() && pkg.manifest_path.starts_with(root))
+            .filter(|pkg| {
+                let id = PackageId::from(pkg.id.clone());
+                selected.iter().all(|ids| ids.contains(&id))
+            })
+            .sorted_by(|a, b| Ord::cmp(&a.name, &b.name))
+            .collect();
+
+        let kept = kept_packages(&workspace, &packages, &opts.keep_source)?;
+
+        for id in kept.iter() {
+            workspace.get_package_mut(id).expect("kept packages are members").keep_source = true;
+        }
+
+        let root_manifest = root.join("Cargo.toml");
+
+        match &selected {
+            Some(_) => {
+                let members = packages
+                    .iter()
+                    .filter(|pkg| metadata.workspace_members.contains(&pkg.id))
+                    .filter_map(|pkg| pkg.manifest_path.parent());
+                let data = scoped_root_manifest(root, members)?;
+                let mut header = data_header(data.len() as u64);
+
+                ar.append_data(&mut header, "Cargo.toml", data.as_bytes())
+                    .context("adding root manifest to archive")?;
+            }
+            None if metadata.root_package().is_none() => {
+                ar.append_file("Cargo.toml")
+                    .context("adding root manifest to archive")?;
+            }
+            None => {}
+        }
+
+        match &selected {
+            Some(_) => {
+                let data = pruned_cargo_lock(root, &packages)?;
+                let mut header = data_header(data.len() as u64);
+
+                ar.append_data(&mut header, CARGO_LOCK_NAME, data.as_bytes())
+                    .context("adding Cargo.lock to archive")?;
+            }
+            None => ar.append_file(CARGO_LOCK_NAME)?,
+        }
+
+        let config_files = discover_config_files(
+            root,
+            packages.iter().filter_map(|pkg| pkg.manifest_path.parent()),
+        )?;
+
+        let exclude_files = opts
+            .exclude_files
+            .iter()
+            .map(|glob| Pattern::new(glob).context(format!("parsing exclude glob: {}", glob)))
+            .collect::<Result<Vec<Pattern>>>()?;
+        // Like include globs, `*` does not match across directories.
+        let match_options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let is_excluded = |path: &Utf8Path| -> bool {
+            let excluded = exclude_files
+                .iter()
+                .any(|glob| glob.matches_with(path.as_str(), match_options));
+            if excluded {
+                debug!("Excluding file: {}", path);
+            }
+            excluded
+        };
+
+        let config_files: Vec<_> = config_files
+            .into_iter()
+            .filter(|config| !is_excluded(&config.path))
+            .collect();
+
+        // Manifests and targets are always generated from the package
+        // metadata, so they can't be replaced by referenced or
+        // included files. Build outputs and the archive itself are
+        // never added.
+        let package_files: HashSet<&Utf8Path> = packages
             .iter()
-            .sorted_by(|a, b| Ord::cmp(&a.name, &b.name));
+            .flat_map(|pkg| {
+                pkg.targets
+                    .iter()
+                    .map(|target| &target.src_path)
+                    .chain(Some(&pkg.manifest_path))
+            })
+            .filter_map(|path| path.strip_prefix(root).ok())
+            .collect();
+        let target_dir = relative_path(root, &metadata.target_directory);
+        let out_path = out_path.map(|path| relative_path(root, path));
+        let is_reserved = |path: &Utf8Path| -> bool {
+            package_files.contains(path)
+                || path == CARGO_LOCK_NAME
+                || path == LOCKFILE_NAME
+                || path == "Cargo.toml"
+                || path.starts_with(&target_dir)
+                || path.components().any(|component| component.as_str() == ".git")
+                || out_path.as_deref() == Some(path)
+        };
 
-        for target in targets {
-            let path = target.src_path.strip_prefix(root).unwrap();
-            let data = target_stub(target);
+        for config in config_files.iter() {
+            if is_credentials_file(&config.path) {
+                bail!(
+                    "Refusing to add credentials file to skeleton archive: {}",
+                    config.path
+                );
+            }
+
+            let table = match &config.table {
+                Some(table) if !find_secrets(table).is_empty() => table,
+                _ => {
+                    ar.append_file(&config.path)
+                        .context(format!("adding config file to archive: {}", config.path))?;
+                    continue;
+                }
+            };
+
+            if !opts.scrub_secrets {
+                bail!(
+                    "Config file {} contains secrets ({}). Remove them or pass `--scrub-secrets` to strip them from the skeleton archive",
+                    config.path,
+                    find_secrets(table).iter().join(", ")
+                );
+            }
+
+            let mut table = table.clone();
+            for key in scrub_secrets(&mut table) {
+                warn!("Removed secret `{}` from config file {}", key, config.path);
+            }
+
+            let data = toml::to_string(&table).context("serializing config file")?;
             let mut header = data_header(data.len() as u64);
 
-            ar.append_data(&mut header, path, data.as_bytes())
-                .context(format!("Adding target stub to archive: {}", path))?;
+            ar.append_data(&mut header, &config.path, data.as_bytes())
+                .context(format!("adding config file to archive: {}", config.path))?;
         }
+
+        let referenced_files: Vec<Utf8PathBuf> = find_referenced_files(root, &config_files)
+            .into_iter()
+            .filter(|path| !config_files.iter().any(|config| &config.path == path))
+            .filter(|path| !is_reserved(path) && !is_excluded(path))
+            .collect();
+
+        for path in referenced_files.iter() {
+            if is_credentials_file(path) {
+                bail!("Refusing to add credentials file to skeleton archive: {}", path);
+            }
+
+            ar.append_file(path)
+                .context(format!("adding referenced file to archive: {}", path))?;
+        }
+
+        let include_files = find_included_files(root, &opts.include_files)?
+            .into_iter()
+            .filter(|path| {
+                !is_reserved(path)
+                    && !config_files.iter().any(|config| &config.path == path)
+                    && !referenced_files.contains(path)
+            })
+            .filter(|path| !is_excluded(path));
+
+        for path in include_files {
+            if is_credentials_file(&path) {
+                bail!("Refusing to add credentials file to skeleton archive: {}", path);
+            }
+
+            debug!("Including file: {}", path);
+
+            ar.append_file(&path)
+                .context(format!("adding included file to archive: {}", path))?;
+        }
+
+        let mut stubs = BTreeSet::new();
+
+        for package in packages.iter() {
+            let path = package.manifest_path.strip_prefix(root).unwrap();
+
+            // A scoped skeleton already has a rewritten root manifest.
+            if selected.is_none() || package.manifest_path != root_manifest {
+                ar.append_file(path)
+                    .context(format!("adding package manifest to archive: {}", path))?;
+            }
+
+            if kept.contains(&PackageId::from(package.id.clone())) {
+                info!("Keeping source for package: {}", package.name);
+
+                let source_files = package_source_files(root, package)?
+                    .into_iter()
+                    .filter(|path| !is_excluded(path));
+
+                for path in source_files {
+                    ar.append_file(&path)
+                        .context(format!("adding package source to archive: {}", path))?;
+                }
+
+                continue;
+            }
+
+            let targets = package
+                .targets
+                .iter()
+                .sorted_by(|a, b| Ord::cmp(&a.name, &b.name));
+
+            for target in targets {
+                let path = target.src_path.strip_prefix(root).unwrap();
+                let data = target_stub(target);
+                let mut header = data_header(data.len() as u64);
+
+                ar.append_data(&mut header, path, data.as_bytes())
+                    .context(format!("Adding target stub to archive: {}", path))?;
+
+                stubs.insert(path.to_owned());
+            }
+        }
+
+        // Patches that no dependency uses are not in the metadata,
+        // but Cargo still loads their manifests.
+        let package_dirs: HashSet<&Utf8Path> = packages
+            .iter()
+            .filter_map(|pkg| pkg.manifest_path.parent())
+            .filter_map(|dir| dir.strip_prefix(root).ok())
+            .collect();
+
+        for dir in find_referenced_packages(root, &config_files)? {
+            if package_dirs.contains(dir.as_path()) {
+                continue;
+            }
+
+            debug!("Adding patched package: {}", dir);
+
+            let path = dir.join("Cargo.toml");
+            ar.append_file(&path)
+                .context(format!("adding patched package manifest to archive: {}", path))?;
+
+            for (path, data) in patched_package_stubs(root, &dir)? {
+                let mut header = data_header(data.len() as u64);
+
+                ar.append_data(&mut header, &path, data.as_bytes())
+                    .context(format!("Adding target stub to archive: {}", path))?;
+
+                stubs.insert(path);
+            }
+        }
+
+        let mut lockfile = workspace.into_lockfile();
+        lockfile.stubs = stubs.into_iter().collect();
+
+        lockfile.sort();
+        let lock_data = lockfile.to_toml();
+        let mut header = data_header(lock_data.len() as u64);
+
+        ar.append_data(&mut header, LOCKFILE_NAME, lock_data.as_bytes())
+            .context("Adding skeleton lockfile to archive")?;
+
+        let (writer, mut report) = ar.finish().context("building tar archive")?;
+        report.packages = lockfile.packages.iter().map(|pkg| pkg.name.clone()).collect();
+
+        Ok((writer, lockfile, report))
     }
+}
 
-    let workspace = {
-        let mut workspace = Workspace::new(root.clone());
-        workspace.load_metadata(&metadata)?;
-        workspace
+/// A tar archive of files in the workspace root. The paths of the
+/// entries and a digest of the written bytes are recorded for the
+/// report.
+struct ArchiveWriter<W: Write> {
+    root: Utf8PathBuf,
+    ar: tar::Builder<DigestWriter<W>>,
+    entries: Vec<Utf8PathBuf>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    fn new(root: &Utf8Path, writer: W) -> Self {
+        let writer = DigestWriter {
+            inner: writer,
+            hasher: Sha256::new(),
+            size: 0,
+        };
+
+        Self {
+            root: root.to_owned(),
+            ar: tar::Builder::new(writer),
+            entries: vec![],
+        }
+    }
+
+    /// Add a generated file.
+    fn append_data(
+        &mut self,
+        header: &mut tar::Header,
+        path: impl AsRef<Utf8Path>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+
+        self.ar.append_data(header, path, data)?;
+        self.entries.push(path.to_owned());
+
+        Ok(())
+    }
+
+    /// Add a file from the workspace with a normalized header, so the
+    /// archive only changes when the file contents do.
+    fn append_file(&mut self, path: impl AsRef<Utf8Path>) -> Result<()> {
+        let path = path.as_ref();
+        let full_path = self.root.join(path);
+        let data = fs::read(&full_path)?;
+        let mut header = data_header(data.len() as u64);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if fs::metadata(&full_path)?.permissions().mode() & 0o111 != 0 {
+                header.set_mode(0o755);
+                header.set_cksum();
+            }
+        }
+
+        self.append_data(&mut header, path, &data[..])?;
+
+        Ok(())
+    }
+
+    /// Finish the archive, returning the sink and a report without
+    /// the package names.
+    fn finish(self) -> Result<(W, CreateReport)> {
+        let writer = self.ar.into_inner()?;
+
+        let report = CreateReport {
+            packages: vec![],
+            entries: self.entries,
+            size: writer.size,
+            digest: format!("{:x}", writer.hasher.finalize()),
+        };
+
+        Ok((writer.inner, report))
+    }
+}
+
+/// Hashes the bytes written to the inner sink.
+struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Find the selected packages and every local package they depend
+/// on through a path dependency. The root package is always included
+/// since it is part of the root manifest.
+fn member_closure(
+    metadata: &Metadata,
+    workspace: &Workspace,
+    packages: impl IntoIterator<Item = PackageId>,
+) -> Result<HashSet<PackageId>> {
+    let mut stack: Vec<PackageId> = packages.into_iter().collect();
+
+    stack.extend(metadata.root_package().map(|pkg| pkg.id.clone().into()));
+
+    let mut ids = HashSet::new();
+
+    while let Some(id) = stack.pop() {
+        if !workspace.is_member(&id) || !ids.insert(id.clone()) {
+            continue;
+        }
+
+        let package = metadata
+            .packages
+            .iter()
+            .find(|pkg| pkg.id.repr == id.as_str())
+            .ok_or_else(|| anyhow!("Missing package metadata for {}", id))?;
+
+        // Every path dependency has to be present for Cargo to load
+        // the workspace, even if it is optional or a dev dependency.
+        let dep_ids = package
+            .dependencies
+            .iter()
+            .filter_map(|dep| dep.path.as_ref())
+            .filter_map(|path| {
+                metadata
+                    .packages
+                    .iter()
+                    .find(|pkg| pkg.manifest_path.parent() == Some(path))
+            })
+            .map(|pkg| pkg.id.clone().into());
+
+        stack.extend(dep_ids);
+    }
+
+    Ok(ids)
+}
+
+/// Find the packages whose source is kept, either because they were
+/// requested or because their manifest sets
+/// `package.metadata.skeleton.keep-source`.
+///
+/// Kept packages are compiled with the dependencies, so every local
+/// package they depend on has to be kept too.
+fn kept_packages(
+    workspace: &Workspace,
+    packages: &[&cargo_metadata::Package],
+    specs: &[String],
+) -> Result<HashSet<PackageId>> {
+    let mut kept: HashSet<PackageId> = workspace
+        .get_package_ids(specs)?
+        .into_iter()
+        .cloned()
+        .collect();
+
+    kept.extend(
+        packages
+            .iter()
+            .filter(|pkg| pkg.metadata["skeleton"]["keep-source"].as_bool() == Some(true))
+            .map(|pkg| pkg.id.clone().into()),
+    );
+
+    for id in kept.iter() {
+        let package = &workspace[id];
+        let stubbed = package
+            .dependencies
+            .iter()
+            .find(|dep| workspace.is_member(dep) && !kept.contains(*dep));
+
+        if let Some(dep) = stubbed {
+            bail!(
+                "Package {} keeps its source but depends on {}, which does not",
+                package.name,
+                workspace[dep].name
+            );
+        }
+    }
+
+    Ok(kept)
+}
+
+/// List the source files of a package, relative to the workspace
+/// root. Cargo decides which files belong to the package, so the
+/// manifest's `include` and `exclude` lists are respected.
+///
+/// Cargo refuses to list some packages, e.g. ones with a path
+/// dependency without a version. Their files are found by walking
+/// the package directory instead.
+fn package_source_files(
+    root: &Utf8Path,
+    package: &cargo_metadata::Package,
+) -> Result<Vec<Utf8PathBuf>> {
+    let cargo = env::var("CARGO").unwrap_or("cargo".into());
+    let package_root = package
+        .manifest_path
+        .parent()
+        .expect("manifest has a parent directory");
+
+    debug!("Running `cargo package --list` for {}", package.name);
+
+    let output = Command::new(&cargo)
+        .args(["package", "--list", "--allow-dirty", "--offline", "--manifest-path"])
+        .arg(&package.manifest_path)
+        .output()
+        .context("executing `cargo package --list` command")?;
+
+    if !output.status.success() {
+        debug!(
+            "Failed to list package files for {}, walking the package directory: {}",
+            package.name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        return walk_package_files(root, package_root);
+    }
+
+    let stdout = String::from_utf8(output.stdout).context("reading package file list")?;
+
+    // Files generated by `cargo package` (e.g. `Cargo.toml.orig`) do
+    // not exist on disk, and the manifest is added separately.
+    let files = stdout
+        .lines()
+        .filter(|line| *line != "Cargo.toml")
+        .map(|line| package_root.join(line))
+        .filter(|path| path.is_file())
+        .map(|path| path.strip_prefix(root).unwrap().to_owned())
+        .collect();
+
+    Ok(files)
+}
+
+/// List the files of a package directory the way `cargo package`
+/// would, applying the manifest's `include` or `exclude` patterns.
+///
+/// Nested packages, the `target` directory and version control
+/// directories are skipped. The manifest is added separately.
+pub(crate) fn walk_package_files(root: &Utf8Path, package_root: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let manifest_path = package_root.join("Cargo.toml");
+    let manifest: toml::Table = fs::read_to_string(&manifest_path)
+        .context(format!("reading {}", manifest_path))?
+        .parse()
+        .context(format!("parsing {}", manifest_path))?;
+
+    let patterns = |key: &str| -> Vec<String> {
+        manifest
+            .get("package")
+            .and_then(|package| package.get(key))
+            .and_then(|patterns| patterns.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|pattern| pattern.as_str().map(String::from))
+            .collect()
+    };
+    let include = patterns("include");
+    let exclude = patterns("exclude");
+
+    let mut files = vec![];
+    let mut dirs = vec![package_root.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in dir.read_dir_utf8().context(format!("reading directory {}", dir))? {
+            let entry = entry.context(format!("reading directory {}", dir))?;
+            let path = entry.path();
+            let relative = path.strip_prefix(package_root).unwrap();
+
+            if path.is_dir() {
+                if relative != "target"
+                    && !VCS_DIRS.contains(&entry.file_name())
+                    && !path.join("Cargo.toml").is_file()
+                {
+                    dirs.push(path.to_owned());
+                }
+                continue;
+            }
+
+            let packaged = if include.is_empty() {
+                !exclude.iter().any(|pattern| matches_package_pattern(pattern, relative))
+            } else {
+                include.iter().any(|pattern| matches_package_pattern(pattern, relative))
+            };
+
+            if packaged && relative != "Cargo.toml" {
+                files.push(relative_path(root, path));
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Returns true if a path relative to the package root matches an
+/// `include` or `exclude` pattern, which use gitignore syntax.
+///
+/// A pattern without a slash matches the name of the file or of any
+/// directory it is in. Any other pattern is matched against the path
+/// from the package root, and also matches the files of a matching
+/// directory.
+fn matches_package_pattern(pattern: &str, path: &Utf8Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    let Ok(glob) = Pattern::new(pattern.trim_start_matches('/').trim_end_matches('/')) else {
+        return false;
+    };
+
+    if anchored {
+        path.ancestors()
+            .filter(|path| !path.as_str().is_empty())
+            .any(|path| glob.matches_with(path.as_str(), options))
+    } else {
+        path.components()
+            .any(|component| glob.matches_with(component.as_str(), options))
+    }
+}
+
+/// Find the targets of a patched package that is not in the metadata
+/// and return a stub for each, so Cargo can load its manifest.
+///
+/// Explicit target paths are read from the manifest. Otherwise
+/// `src/lib.rs` and `src/main.rs` are stubbed if they exist, and
+/// `src/lib.rs` is stubbed if neither does.
+fn patched_package_stubs(
+    root: &Utf8Path,
+    dir: &Utf8Path,
+) -> Result<Vec<(Utf8PathBuf, &'static str)>> {
+    let manifest_path = root.join(dir).join("Cargo.toml");
+    let contents = fs::read_to_string(&manifest_path)
+        .context(format!("reading manifest: {}", manifest_path))?;
+    let manifest: toml::Table =
+        toml::from_str(&contents).context(format!("parsing manifest: {}", manifest_path))?;
+
+    let mut stubs = BTreeSet::new();
+
+    if let Some(path) = manifest
+        .get("lib")
+        .and_then(|lib| lib.get("path"))
+        .and_then(|path| path.as_str())
+    {
+        stubs.insert((dir.join(path), LIB_STUB));
+    }
+
+    for kind in ["bin", "example", "test", "bench"] {
+        let paths = manifest
+            .get(kind)
+            .and_then(|targets| targets.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|target| target.get("path").and_then(|path| path.as_str()));
+
+        for path in paths {
+            stubs.insert((dir.join(path), BIN_STUB));
+        }
+    }
+
+    for (path, stub) in [("src/lib.rs", LIB_STUB), ("src/main.rs", BIN_STUB)] {
+        if root.join(dir).join(path).is_file() {
+            stubs.insert((dir.join(path), stub));
+        }
+    }
+
+    if stubs.is_empty() {
+        stubs.insert((dir.join("src/lib.rs"), LIB_STUB));
+    }
+
+    Ok(stubs.into_iter().collect())
+}
+
+/// Read the workspace's Cargo.lock and prune it to the packages
+/// reachable from the given local packages.
+///
+/// The dependency graph recorded in Cargo.lock is used rather than
+/// the resolve graph from Cargo metadata. Cargo locks dependencies
+/// for every feature and platform, while the metadata only includes
+/// the features that were selected, and `--locked` builds fail if
+/// any of them are missing.
+fn pruned_cargo_lock(root: &Utf8Path, packages: &[&cargo_metadata::Package]) -> Result<String> {
+    let contents =
+        fs::read_to_string(root.join(CARGO_LOCK_NAME)).context("reading Cargo.lock")?;
+    let mut cargo_lock = CargoLock::parse(&contents)?;
+
+    let roots: Vec<(&str, String)> = packages
+        .iter()
+        .map(|pkg| (pkg.name.as_str(), pkg.version.to_string()))
+        .collect();
+
+    cargo_lock.prune(
+        roots
+            .iter()
+            .map(|(name, version)| (*name, version.as_str())),
+    )?;
+
+    Ok(cargo_lock.to_string())
+}
+
+/// Rewrite the root manifest so the workspace only has the given
+/// members.
+fn scoped_root_manifest<'a>(
+    root: &Utf8Path,
+    member_dirs: impl Iterator<Item = &'a Utf8Path>,
+) -> Result<String> {
+    let contents =
+        fs::read_to_string(root.join("Cargo.toml")).context("reading root manifest")?;
+    let mut manifest: toml::Table = toml::from_str(&contents).context("parsing root manifest")?;
+
+    let workspace = match manifest.get_mut("workspace").and_then(|ws| ws.as_table_mut()) {
+        Some(workspace) => workspace,
+        None => return Ok(contents),
     };
-    let mut lockfile = workspace.into_lockfile();
 
-    let lock_data = lockfile.to_string();
-    let mut header = data_header(lock_data.len() as u64);
+    let members: Vec<toml::Value> = member_dirs
+        .filter(|dir| *dir != root)
+        .map(|dir| dir.strip_prefix(root).unwrap().as_str().into())
+        .sorted_by(|a: &toml::Value, b| Ord::cmp(&a.as_str(), &b.as_str()))
+        .collect();
+
+    workspace.insert("members".into(), members.into());
+    workspace.remove("default-members");
+
+    toml::to_string(&manifest).context("serializing root manifest")
+}
+
+/// Find the files matching the include globs, relative to the
+/// workspace root, in a stable order.
+fn find_included_files(root: &Utf8Path, globs: &[String]) -> Result<Vec<Utf8PathBuf>> {
+    let mut files = BTreeSet::new();
+
+    for pattern in globs.iter() {
+        let full_pattern = format!("{}/{}", Pattern::escape(root.as_str()), pattern);
+        let paths =
+            glob::glob(&full_pattern).context(format!("parsing include glob: {}", pattern))?;
+        let mut matched = false;
 
-    ar.append_data(&mut header, LOCKFILE_NAME, lock_data.as_bytes())
-        .context("Adding skeleton lockfile to archive")?;
+        for path in paths {
+            let path = path.context(format!("reading files matching {}", pattern))?;
+            let path = Utf8PathBuf::try_from(path).context("path should be utf-8")?;
+
+            if path.is_file() {
+                matched = true;
+                files.insert(relative_path(root, &path));
+            }
+        }
 
-    ar.into_inner().context("building tar archive")?;
+        if !matched {
+            warn!("Include glob `{}` did not match any files", pattern);
+        }
+    }
 
-    Ok(())
+    Ok(files.into_iter().collect())
 }
 
 fn data_header(size: u64) -> tar::Header {
@@ -116,6 +1064,11 @@ fn data_header(size: u64) -> tar::Header {
     header
 }
 
+/// Returns true if the contents are a target stub.
+pub(crate) fn is_stub(contents: &str) -> bool {
+    contents == LIB_STUB || contents == BIN_STUB
+}
+
 fn target_stub(target: &Target) -> &'static str {
     if target.is_bin() || target.is_bench() || target.is_test() || target.is_example() {
         BIN_STUB
@@ -123,3 +1076,394 @@ fn target_stub(target: &Target) -> &'static str {
         LIB_STUB
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn metadata(root: &Utf8Path) -> Metadata {
+        cargo_metadata::MetadataCommand::new()
+            .current_dir(root)
+            .other_options(vec!["--offline".to_string()])
+            .exec()
+            .unwrap()
+    }
+
+    fn write_package(root: &Utf8Path, dir: &str, dependencies: &str) {
+        let name = dir.rsplit('/').next().unwrap();
+
+        write(
+            root,
+            &format!("{}/Cargo.toml", dir),
+            &format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n{}",
+                name, dependencies
+            ),
+        );
+        write(root, &format!("{}/src/lib.rs", dir), "");
+    }
+
+    fn package_id(metadata: &Metadata, name: &str) -> PackageId {
+        let package = metadata.packages.iter().find(|pkg| pkg.name == name).unwrap();
+
+        package.id.clone().into()
+    }
+
+    #[test]
+    fn finds_member_closure() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\n");
+        write_package(root, "crates/a", "[dependencies]\nb = { path = \"../b\" }\n");
+        write_package(
+            root,
+            "crates/b",
+            "[dev-dependencies]\nc = { path = \"../c\" }\n\n\
+             [target.'cfg(windows)'.dependencies]\nd = { path = \"../d\", optional = true }\n",
+        );
+        write_package(root, "crates/c", "");
+        write_package(root, "crates/d", "");
+        write_package(root, "crates/e", "[dependencies]\na = { path = \"../a\" }\n");
+
+        let metadata = metadata(root);
+        let mut workspace = Workspace::new(root.to_owned());
+        workspace.load_metadata(&metadata).unwrap();
+
+        let ids = member_closure(&metadata, &workspace, [package_id(&metadata, "a")]).unwrap();
+
+        let mut names: Vec<&str> = ids.iter().map(|id| workspace[id].name.as_str()).collect();
+        names.sort();
+
+        assert_eq!(vec!["a", "b", "c", "d"], names);
+    }
+
+    #[test]
+    fn member_closure_includes_root_package() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [workspace]\nmembers = [\"crates/*\"]\n",
+        );
+        write(root, "src/main.rs", "fn main() {}");
+        write_package(root, "crates/a", "");
+        write_package(root, "crates/b", "");
+
+        let metadata = metadata(root);
+        let mut workspace = Workspace::new(root.to_owned());
+        workspace.load_metadata(&metadata).unwrap();
+
+        let ids = member_closure(&metadata, &workspace, [package_id(&metadata, "b")]).unwrap();
+
+        let mut names: Vec<&str> = ids.iter().map(|id| workspace[id].name.as_str()).collect();
+        names.sort();
+
+        assert_eq!(vec!["app", "b"], names);
+    }
+
+    #[test]
+    fn scopes_root_manifest_members() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "app"
+            version = "0.1.0"
+
+            [workspace]
+            members = ["crates/*"]
+            default-members = ["crates/a"]
+            resolver = "2"
+
+            [workspace.dependencies]
+            serde = "1"
+            "#,
+        );
+
+        let dirs = [root.join("crates/b"), root.to_owned(), root.join("crates/a")];
+        let data = scoped_root_manifest(root, dirs.iter().map(|dir| dir.as_path())).unwrap();
+        let manifest: toml::Table = toml::from_str(&data).unwrap();
+
+        let expected: toml::Table = toml::from_str(
+            r#"
+            [package]
+            name = "app"
+            version = "0.1.0"
+
+            [workspace]
+            members = ["crates/a", "crates/b"]
+            resolver = "2"
+
+            [workspace.dependencies]
+            serde = "1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(expected, manifest);
+    }
+
+    #[test]
+    fn keeps_manifest_without_workspace() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        let contents = "[package]\nname = \"app\"\nversion = \"0.1.0\"\n";
+        write(root, "Cargo.toml", contents);
+
+        let data = scoped_root_manifest(root, [root].into_iter()).unwrap();
+
+        assert_eq!(contents, data);
+    }
+
+    #[test]
+    fn normalizes_entry_headers() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\n");
+        write_package(root, "crates/a", "");
+        write_package(root, "crates/b", "");
+        write(
+            root,
+            ".cargo/config.toml",
+            "[target.x86_64-unknown-linux-gnu]\nlinker = \"tools/linker.sh\"\n",
+        );
+        write(root, "tools/linker.sh", "#!/bin/sh\n");
+        write(root, ".env", "DATABASE_URL=sqlite::memory:\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(root.join("tools/linker.sh"), fs::Permissions::from_mode(0o700))
+                .unwrap();
+        }
+
+        let metadata = metadata(root);
+        let mut archive = vec![];
+
+        SkeletonBuilder::new(metadata)
+            .keep_source(["b"])
+            .include_files([".env"])
+            .write(&mut archive)
+            .unwrap();
+
+        let mut ar = tar::Archive::new(&archive[..]);
+        let mut paths = vec![];
+
+        for entry in ar.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            let path = entry.path().unwrap().to_str().unwrap().to_string();
+
+            assert_eq!(0, header.mtime().unwrap(), "{}", path);
+            assert_eq!(0, header.uid().unwrap(), "{}", path);
+            assert_eq!(0, header.gid().unwrap(), "{}", path);
+
+            let mode = if cfg!(unix) && path == "tools/linker.sh" { 0o755 } else { 0o644 };
+            assert_eq!(mode, header.mode().unwrap(), "{}", path);
+
+            paths.push(path);
+        }
+
+        for path in [".cargo/config.toml", "tools/linker.sh", ".env", "crates/b/src/lib.rs"] {
+            assert!(paths.iter().any(|p| p == path), "missing {}", path);
+        }
+    }
+
+    #[test]
+    fn skips_workspace_files_referenced_by_config() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            r#"
+            [package]
+            name = "app"
+            version = "0.1.0"
+
+            [patch.crates-io]
+            unused = { path = "vendor/unused" }
+            "#,
+        );
+        write(root, "src/main.rs", "fn main() {}");
+        write(
+            root,
+            ".cargo/config.toml",
+            r#"
+            [env]
+            CARGO_WORKSPACE_DIR = { value = "", relative = true }
+            MANIFEST = { value = "Cargo.toml", relative = true }
+            MAIN = { value = "src/main.rs", relative = true }
+            GIT_CONFIG = { value = ".git/config", relative = true }
+            APP = { value = "target/debug/app", relative = true }
+            SKELETON = { value = "skeleton.tar", relative = true }
+            OPENSSL_CONF = { value = "openssl.cnf", relative = true }
+            "#,
+        );
+        write(root, "openssl.cnf", "");
+        write(root, ".git/config", "");
+        write(root, "target/debug/app", "");
+        write(root, "vendor/unused/Cargo.toml", "[package]\nname = \"unused\"\nversion = \"0.1.0\"\n");
+        write(root, "vendor/unused/src/lib.rs", "pub fn real() {}");
+
+        let metadata = metadata(root);
+        let opts = CreateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let report = SkeletonBuilder { metadata, opts }
+            .write_to_path(root.join("skeleton.tar"))
+            .unwrap();
+
+        let entries: Vec<&str> = report.entries.iter().map(|path| path.as_str()).collect();
+
+        assert_eq!(
+            vec![
+                "Cargo.lock",
+                ".cargo/config.toml",
+                "openssl.cnf",
+                "Cargo.toml",
+                "src/main.rs",
+                "vendor/unused/Cargo.toml",
+                "vendor/unused/src/lib.rs",
+                "Skeleton.lock",
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn failed_verification_keeps_previous_archive() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+        let ws_root = root.join("ws");
+
+        // The path dependency outside the workspace is not packed, so
+        // the skeleton does not resolve on its own.
+        write(
+            &ws_root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\noutside = { path = \"../outside\" }\n",
+        );
+        write(&ws_root, "src/main.rs", "fn main() {}");
+        write_package(root, "outside", "");
+        write(&ws_root, "skeleton.tar", "previous");
+
+        let metadata = metadata(&ws_root);
+        let opts = CreateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        SkeletonBuilder { metadata, opts }
+            .write_to_path(ws_root.join("skeleton.tar"))
+            .unwrap_err();
+
+        assert_eq!("previous", fs::read_to_string(ws_root.join("skeleton.tar")).unwrap());
+
+        let mut files: Vec<String> = ws_root
+            .read_dir_utf8()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(vec!["Cargo.lock", "Cargo.toml", "skeleton.tar", "src"], files);
+    }
+
+    #[test]
+    fn keeps_source_of_unpackageable_member() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\n");
+        // `cargo package` refuses path dependencies without a version.
+        write(
+            root,
+            "crates/proto-types/Cargo.toml",
+            "[package]\nname = \"proto-types\"\nversion = \"0.1.0\"\npublish = false\n\
+             exclude = [\"fixtures/\", \"*.bin\"]\n\n\
+             [dependencies]\ncommon = { path = \"../common\" }\n",
+        );
+        write(root, "crates/proto-types/build.rs", "fn main() {}");
+        write(root, "crates/proto-types/src/lib.rs", "mod gen;");
+        write(root, "crates/proto-types/src/gen/mod.rs", "");
+        write(root, "crates/proto-types/src/gen/data.bin", "");
+        write(root, "crates/proto-types/fixtures/big.json", "");
+        write(root, "crates/proto-types/target/debug/out", "");
+        write_package(root, "crates/common", "");
+
+        let metadata = metadata(root);
+        let package = metadata.packages.iter().find(|pkg| pkg.name == "proto-types").unwrap();
+
+        let expected = vec![
+            "crates/proto-types/build.rs",
+            "crates/proto-types/src/gen/mod.rs",
+            "crates/proto-types/src/lib.rs",
+        ];
+
+        // Recent Cargo versions list the package, older ones fail and
+        // the directory is walked instead.
+        let mut files = package_source_files(root, package).unwrap();
+        files.sort();
+        assert_eq!(expected, files.iter().map(|path| path.as_str()).collect::<Vec<_>>());
+
+        let files = walk_package_files(root, &root.join("crates/proto-types")).unwrap();
+        assert_eq!(expected, files.iter().map(|path| path.as_str()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn matches_package_patterns() {
+        let matches = |pattern, path| matches_package_pattern(pattern, Utf8Path::new(path));
+
+        assert!(matches("*.bin", "data.bin"));
+        assert!(matches("*.bin", "src/gen/data.bin"));
+        assert!(matches("fixtures/", "fixtures/big.json"));
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/gen/mod.rs"));
+        assert!(matches("/src/**/*.rs", "src/gen/mod.rs"));
+        assert!(matches("src", "src/gen/mod.rs"));
+        assert!(!matches("src", "tests/src.rs"));
+    }
+
+    #[test]
+    fn excludes_files_per_directory() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\n");
+        write(root, "src/main.rs", "fn main() {}");
+        write(root, "config/app.toml", "");
+        write(root, "config/nested/db.toml", "");
+
+        let metadata = metadata(root);
+        let mut archive = vec![];
+
+        let report = SkeletonBuilder::new(metadata)
+            .include_files(["config/*", "config/nested/*"])
+            .exclude_files(["config/*"])
+            .write(&mut archive)
+            .unwrap();
+
+        assert!(report.entries.iter().any(|path| path == "config/nested/db.toml"));
+        assert!(!report.entries.iter().any(|path| path == "config/app.toml"));
+    }
+}
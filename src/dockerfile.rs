@@ -0,0 +1,579 @@
+use std::{fmt::Write, fs};
+
+use anyhow::{bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{CargoOpt, Metadata, Package};
+use itertools::Itertools;
+use log::*;
+use serde::Deserialize;
+
+use crate::{
+    build::shell_quote,
+    config::{relative_path, TOOLCHAIN_NAMES},
+    create::DEFAULT_OUT_PATH,
+};
+
+/// The image distributing the `cargo-skeleton` binary.
+pub const DEFAULT_SKELETON_IMAGE: &str = "cargo-skeleton:latest";
+
+/// The image the built binaries are copied into.
+pub const DEFAULT_RUNTIME_IMAGE: &str = "docker.io/debian:bookworm-slim";
+
+/// The directory the workspace is copied to in the image.
+const WORKDIR: &str = "/usr/src/app";
+
+/// The target directory in the image, relative to the workspace. It
+/// is passed to cargo explicitly, so the output does not depend on
+/// the target directory configured on the host.
+const TARGET_DIR: &str = "target";
+
+const HEADER_COMMENT: &str = "\
+# This file is automatically @generated by Cargo Skeleton.
+# Regenerate it with `cargo skeleton dockerfile` and check that it is
+# up to date with `cargo skeleton dockerfile --check`.
+";
+
+#[derive(Debug, Default)]
+pub struct DockerfileOptions {
+    /// Names of the binary packages to build, or every workspace
+    /// member with a binary target if empty
+    pub packages: Vec<String>,
+
+    /// Feature flags to build the packages with
+    pub features: Vec<CargoOpt>,
+
+    /// Profile to build the packages with [default: release]
+    pub profile: Option<String>,
+
+    /// Target triple to build the packages for
+    pub target: Option<String>,
+
+    /// Path of the skeleton archive, relative to the workspace root
+    pub archive_path: Option<Utf8PathBuf>,
+
+    /// Image for the build stages, derived from the toolchain file
+    /// if not given
+    pub base_image: Option<String>,
+
+    /// Image the binaries are copied into
+    pub runtime_image: Option<String>,
+
+    /// Image to copy the `cargo-skeleton` binary from
+    pub skeleton_image: Option<String>,
+}
+
+/// A generated Dockerfile and the matching `.dockerignore`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DockerFiles {
+    pub dockerfile: String,
+    pub dockerignore: String,
+}
+
+/// The toolchain pinned with a `rust-toolchain.toml` or
+/// `rust-toolchain` file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Toolchain {
+    /// Path of the toolchain file, relative to the workspace root
+    path: Utf8PathBuf,
+
+    /// The channel, e.g. `1.80.1` or `nightly-2024-09-01`
+    channel: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolchainFile {
+    toolchain: ToolchainSection,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolchainSection {
+    channel: Option<String>,
+}
+
+/// Generate a multi-stage Dockerfile building the binaries of a
+/// workspace with a skeleton, along with a `.dockerignore` for the
+/// build context.
+///
+/// The output only depends on the workspace and the options, so it
+/// can be checked into the repository. `dockerfile_path` is where the
+/// Dockerfile will be written, so it can be left out of the context.
+pub fn generate_dockerfile(
+    metadata: &Metadata,
+    opts: &DockerfileOptions,
+    dockerfile_path: &Utf8Path,
+) -> Result<DockerFiles> {
+    let root = &metadata.workspace_root;
+
+    let packages = binary_packages(metadata, &opts.packages)?;
+    let toolchain = find_toolchain(root)?;
+
+    let profile = opts.profile.as_deref().unwrap_or("release");
+    let archive_path = opts
+        .archive_path
+        .clone()
+        .unwrap_or_else(|| DEFAULT_OUT_PATH.into());
+    let base_image = match &opts.base_image {
+        Some(image) => image.clone(),
+        None => base_image(toolchain.as_ref()),
+    };
+    let runtime_image = opts
+        .runtime_image
+        .as_deref()
+        .unwrap_or(DEFAULT_RUNTIME_IMAGE);
+    let skeleton_image = opts
+        .skeleton_image
+        .as_deref()
+        .unwrap_or(DEFAULT_SKELETON_IMAGE);
+
+    let features = feature_args(&opts.features);
+    let package_args: Vec<String> = packages
+        .iter()
+        .flat_map(|pkg| ["--package".to_string(), pkg.name.clone()])
+        .collect();
+    let mut target_args = vec!["--profile".to_string(), profile.to_string()];
+    if let Some(target) = &opts.target {
+        target_args.extend(["--target".to_string(), target.clone()]);
+    }
+
+    // Scope the skeleton only if packages were selected explicitly,
+    // so the workspace settings apply otherwise.
+    let scope_args = if opts.packages.is_empty() {
+        vec![]
+    } else {
+        package_args.clone()
+    };
+
+    let mut create = vec!["cargo".to_string(), "skeleton".into(), "create".into()];
+    create.extend(scope_args.iter().cloned());
+    create.extend(features.iter().cloned());
+    if opts.archive_path.is_some() {
+        create.extend(["--out-path".to_string(), archive_path.to_string()]);
+    }
+
+    let mut unpack = vec!["cargo".to_string(), "skeleton".into(), "unpack".into()];
+    if opts.archive_path.is_some() {
+        unpack.extend(["--archive-path".to_string(), archive_path.to_string()]);
+    }
+
+    let mut skeleton_build = vec!["cargo".to_string(), "skeleton".into(), "build".into()];
+    skeleton_build.extend(scope_args.iter().cloned());
+    skeleton_build.extend(target_args.iter().cloned());
+    skeleton_build.extend([
+        "--".to_string(),
+        "--locked".into(),
+        "--target-dir".into(),
+        TARGET_DIR.into(),
+    ]);
+
+    let mut build = vec!["cargo".to_string(), "build".into(), "--locked".into()];
+    build.extend(["--target-dir".to_string(), TARGET_DIR.into()]);
+    build.extend(package_args.iter().cloned());
+    build.extend(target_args.iter().cloned());
+    build.extend(features.iter().cloned());
+
+    let mut out_dir = Utf8Path::new(WORKDIR).join(TARGET_DIR);
+    if let Some(target) = &opts.target {
+        out_dir.push(target);
+    }
+    out_dir.push(profile_dir(profile));
+
+    let bins: Vec<&str> = packages
+        .iter()
+        .flat_map(|pkg| pkg.targets.iter())
+        .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+        .map(|target| target.name.as_str())
+        .sorted()
+        .dedup()
+        .collect();
+
+    let mut out = String::new();
+
+    out.push_str(HEADER_COMMENT);
+    writeln!(out)?;
+    writeln!(out, "FROM {} AS base", base_image)?;
+    writeln!(out)?;
+    writeln!(out, "ENV CARGO_INCREMENTAL=0")?;
+    writeln!(out, "WORKDIR {}", WORKDIR)?;
+    if let Some(toolchain) = &toolchain {
+        writeln!(out)?;
+        writeln!(out, "COPY {} ./", toolchain.path)?;
+        writeln!(
+            out,
+            "RUN rustup show active-toolchain || rustup toolchain install"
+        )?;
+    }
+    if let Some(target) = &opts.target {
+        writeln!(out)?;
+        writeln!(out, "RUN rustup target add {}", shell_quote(target))?;
+    }
+    writeln!(out)?;
+    writeln!(out, "FROM base AS skeleton-builder")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "COPY --from={} /cargo-skeleton /usr/local/cargo/bin/",
+        skeleton_image
+    )?;
+    writeln!(out, "COPY . .")?;
+    writeln!(out, "RUN {}", shell_words(&create))?;
+    writeln!(out)?;
+    writeln!(out, "FROM base AS builder")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "COPY --from={} /cargo-skeleton /usr/local/cargo/bin/",
+        skeleton_image
+    )?;
+    writeln!(
+        out,
+        "COPY --from=skeleton-builder {dir}/{path} {dir}/{path}",
+        dir = WORKDIR,
+        path = archive_path
+    )?;
+    writeln!(out, "RUN {}", shell_words(&unpack))?;
+    writeln!(out, "RUN {}", shell_words(&skeleton_build))?;
+    writeln!(out)?;
+    writeln!(out, "COPY . .")?;
+    writeln!(out, "RUN {}", shell_words(&build))?;
+    writeln!(out)?;
+    writeln!(out, "FROM {} AS runtime", runtime_image)?;
+    writeln!(out)?;
+    for bin in bins.iter() {
+        writeln!(
+            out,
+            "COPY --from=builder {}/{} /usr/local/bin/",
+            out_dir, bin
+        )?;
+    }
+    if let [bin] = bins[..] {
+        writeln!(out)?;
+        writeln!(out, "ENTRYPOINT [\"/usr/local/bin/{}\"]", bin)?;
+    }
+
+    let mut dockerignore = String::new();
+
+    dockerignore.push_str(HEADER_COMMENT);
+    writeln!(dockerignore)?;
+    writeln!(dockerignore, ".git")?;
+    writeln!(dockerignore, "{}/", TARGET_DIR)?;
+    writeln!(dockerignore, "{}", archive_path)?;
+    if let Ok(path) = dockerfile_path.strip_prefix(root) {
+        writeln!(dockerignore, "{}", path)?;
+        writeln!(
+            dockerignore,
+            "{}",
+            relative_path(root, &dockerignore_path(root, dockerfile_path))
+        )?;
+    }
+
+    Ok(DockerFiles {
+        dockerfile: out,
+        dockerignore,
+    })
+}
+
+/// Returns the path of the ignore file Docker reads for a Dockerfile.
+///
+/// A `Dockerfile` in the root of the build context uses
+/// `.dockerignore`. Any other Dockerfile uses `<Dockerfile>.dockerignore`
+/// next to it, which BuildKit reads in place of the context's
+/// `.dockerignore`.
+pub fn dockerignore_path(root: &Utf8Path, dockerfile_path: &Utf8Path) -> Utf8PathBuf {
+    if dockerfile_path == root.join("Dockerfile") {
+        root.join(".dockerignore")
+    } else {
+        format!("{}.dockerignore", dockerfile_path).into()
+    }
+}
+
+/// Write the generated files, or with `check` compare them to the files
+/// on disk without writing. Returns the paths that were out of date.
+pub fn write_docker_files(
+    files: &DockerFiles,
+    root: &Utf8Path,
+    dockerfile_path: &Utf8Path,
+    check: bool,
+) -> Result<Vec<Utf8PathBuf>> {
+    let mut outdated = vec![];
+
+    for (path, contents) in [
+        (dockerfile_path.to_owned(), &files.dockerfile),
+        (
+            dockerignore_path(root, dockerfile_path),
+            &files.dockerignore,
+        ),
+    ] {
+        if fs::read_to_string(&path).is_ok_and(|current| &current == contents) {
+            debug!("{} is up to date", path);
+            continue;
+        }
+
+        if !check {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).with_context(|| format!("creating {}", dir))?;
+            }
+            fs::write(&path, contents).with_context(|| format!("writing {}", path))?;
+        }
+
+        outdated.push(path);
+    }
+
+    Ok(outdated)
+}
+
+/// Returns the selected workspace members, which must have a binary
+/// target, or every member with a binary target.
+fn binary_packages<'a>(metadata: &'a Metadata, names: &[String]) -> Result<Vec<&'a Package>> {
+    let members = metadata.workspace_packages();
+
+    let has_bin = |pkg: &Package| {
+        pkg.targets
+            .iter()
+            .any(|target| target.kind.iter().any(|kind| kind == "bin"))
+    };
+
+    let packages: Vec<&Package> = if names.is_empty() {
+        members.into_iter().filter(|pkg| has_bin(pkg)).collect()
+    } else {
+        let mut packages = vec![];
+        for name in names {
+            let Some(pkg) = members.iter().find(|pkg| &pkg.name == name) else {
+                bail!("Package {} is not a workspace member", name);
+            };
+            if !has_bin(pkg) {
+                bail!("Package {} has no binary targets", name);
+            }
+            packages.push(*pkg);
+        }
+        packages
+    };
+
+    if packages.is_empty() {
+        bail!("No workspace members have binary targets");
+    }
+
+    Ok(packages
+        .into_iter()
+        .sorted_by(|a, b| Ord::cmp(&a.name, &b.name))
+        .dedup_by(|a, b| a.id == b.id)
+        .collect())
+}
+
+/// Read the toolchain file in the workspace root, if any.
+fn find_toolchain(root: &Utf8Path) -> Result<Option<Toolchain>> {
+    for name in TOOLCHAIN_NAMES {
+        let path = root.join(name);
+
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        let channel = match toml::from_str::<ToolchainFile>(&data) {
+            Ok(file) => file.toolchain.channel,
+            Err(err) if path.extension() == Some("toml") => {
+                return Err(err).with_context(|| format!("parsing {}", path));
+            }
+            // The legacy file format only contains the channel.
+            Err(_) => Some(data.trim().to_string()).filter(|channel| !channel.is_empty()),
+        };
+
+        return Ok(Some(Toolchain {
+            path: relative_path(root, &path),
+            channel,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Returns the official Rust image for a toolchain. Only channels with
+/// a version number have a matching image, any other channel is
+/// installed on top of the latest release.
+fn base_image(toolchain: Option<&Toolchain>) -> String {
+    let channel = toolchain.and_then(|toolchain| toolchain.channel.as_deref());
+
+    match channel {
+        Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => {
+            format!("docker.io/rust:{}-slim-bookworm", version)
+        }
+        _ => "docker.io/rust:slim-bookworm".to_string(),
+    }
+}
+
+/// Returns the name of the directory Cargo writes a profile's
+/// artifacts to.
+fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+/// Returns the cargo flags for the feature selection.
+fn feature_args(features: &[CargoOpt]) -> Vec<String> {
+    let mut args = vec![];
+
+    for opt in features {
+        match opt {
+            CargoOpt::AllFeatures => args.push("--all-features".to_string()),
+            CargoOpt::NoDefaultFeatures => args.push("--no-default-features".to_string()),
+            CargoOpt::SomeFeatures(features) => {
+                args.extend(["--features".to_string(), features.join(",")])
+            }
+        }
+    }
+
+    args
+}
+
+fn shell_words(words: &[String]) -> String {
+    words.iter().map(|word| shell_quote(word)).join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn generates_docker_files() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"core\"]\n",
+        );
+        write(
+            root,
+            "rust-toolchain.toml",
+            "[toolchain]\nchannel = \"1.80.1\"\n",
+        );
+        write(
+            root,
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[features]\njson = []\n\n[[bin]]\nname = \"app-server\"\npath = \"src/main.rs\"\n",
+        );
+        write(root, "app/src/main.rs", "fn main() {}");
+        write(
+            root,
+            "core/Cargo.toml",
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        );
+        write(root, "core/src/lib.rs", "");
+        // The target directory of the host is not used in the image.
+        write(
+            root,
+            ".cargo/config.toml",
+            "[build]\ntarget-dir = \"out\"\n",
+        );
+
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .current_dir(root)
+            .other_options(vec!["--offline".to_string()])
+            .exec()
+            .unwrap();
+        let opts = DockerfileOptions {
+            packages: vec!["app".into()],
+            features: vec![CargoOpt::SomeFeatures(vec!["json".into()])],
+            target: Some("x86_64-unknown-linux-musl".into()),
+            ..Default::default()
+        };
+
+        let files = generate_dockerfile(&metadata, &opts, &root.join("docker/Dockerfile")).unwrap();
+
+        assert_eq!(
+            files.dockerfile,
+            r#"# This file is automatically @generated by Cargo Skeleton.
+# Regenerate it with `cargo skeleton dockerfile` and check that it is
+# up to date with `cargo skeleton dockerfile --check`.
+
+FROM docker.io/rust:1.80.1-slim-bookworm AS base
+
+ENV CARGO_INCREMENTAL=0
+WORKDIR /usr/src/app
+
+COPY rust-toolchain.toml ./
+RUN rustup show active-toolchain || rustup toolchain install
+
+RUN rustup target add x86_64-unknown-linux-musl
+
+FROM base AS skeleton-builder
+
+COPY --from=cargo-skeleton:latest /cargo-skeleton /usr/local/cargo/bin/
+COPY . .
+RUN cargo skeleton create --package app --features json
+
+FROM base AS builder
+
+COPY --from=cargo-skeleton:latest /cargo-skeleton /usr/local/cargo/bin/
+COPY --from=skeleton-builder /usr/src/app/skeleton.tar /usr/src/app/skeleton.tar
+RUN cargo skeleton unpack
+RUN cargo skeleton build --package app --profile release --target x86_64-unknown-linux-musl -- --locked --target-dir target
+
+COPY . .
+RUN cargo build --locked --target-dir target --package app --profile release --target x86_64-unknown-linux-musl --features json
+
+FROM docker.io/debian:bookworm-slim AS runtime
+
+COPY --from=builder /usr/src/app/target/x86_64-unknown-linux-musl/release/app-server /usr/local/bin/
+
+ENTRYPOINT ["/usr/local/bin/app-server"]
+"#
+        );
+        assert_eq!(
+            files.dockerignore,
+            r#"# This file is automatically @generated by Cargo Skeleton.
+# Regenerate it with `cargo skeleton dockerfile` and check that it is
+# up to date with `cargo skeleton dockerfile --check`.
+
+.git
+target/
+skeleton.tar
+docker/Dockerfile
+docker/Dockerfile.dockerignore
+"#
+        );
+    }
+
+    #[test]
+    fn derives_base_image_from_toolchain() {
+        let toolchain = |channel: &str| Toolchain {
+            path: "rust-toolchain.toml".into(),
+            channel: Some(channel.into()),
+        };
+
+        assert_eq!("docker.io/rust:slim-bookworm", base_image(None));
+        assert_eq!(
+            "docker.io/rust:1.80.1-slim-bookworm",
+            base_image(Some(&toolchain("1.80.1")))
+        );
+        assert_eq!(
+            "docker.io/rust:slim-bookworm",
+            base_image(Some(&toolchain("nightly-2024-09-01")))
+        );
+    }
+
+    #[test]
+    fn finds_dockerignore_path() {
+        let root = Utf8Path::new("/app");
+
+        assert_eq!(
+            "/app/.dockerignore",
+            dockerignore_path(root, "/app/Dockerfile".into())
+        );
+        assert_eq!(
+            "/app/docker/Dockerfile.dockerignore",
+            dockerignore_path(root, "/app/docker/Dockerfile".into())
+        );
+    }
+}
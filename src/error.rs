@@ -0,0 +1,118 @@
+use std::process::ExitStatus;
+
+use camino::Utf8PathBuf;
+
+/// Errors returned by this crate.
+///
+/// Failures that callers may want to tell apart have their own
+/// variant. Everything else is [`SkeletonError::Other`], an
+/// [`anyhow::Error`] with context describing what failed.
+///
+/// ```no_run
+/// use cargo_skeleton::{load_lockfile, SkeletonError};
+///
+/// match load_lockfile("/usr/src/app".into()) {
+///     Ok(lockfile) => println!("{} packages", lockfile.packages.len()),
+///     Err(SkeletonError::MissingLockfile { .. }) => println!("not a skeleton"),
+///     Err(err) => eprintln!("{:#}", err),
+/// }
+/// ```
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum SkeletonError {
+    /// The workspace has no `Skeleton.lock`, e.g. because the skeleton
+    /// was not unpacked
+    #[error("Failed to read Skeleton.lock from workspace directory {workspace_root}")]
+    MissingLockfile { workspace_root: Utf8PathBuf },
+
+    /// The contents of a `Skeleton.lock` are not valid
+    #[error("Failed to parse Skeleton.lock")]
+    InvalidLockfile(#[source] toml::de::Error),
+
+    /// The skeleton archive does not exist
+    #[error("Skeleton archive {path} does not exist")]
+    MissingArchive { path: Utf8PathBuf },
+
+    /// The unpack destination contains a workspace that is not a
+    /// skeleton, which unpacking would overwrite
+    #[error("Attempted to unpack a skeleton archive into an existing workspace")]
+    ExistingWorkspace { dest_path: Utf8PathBuf },
+
+    /// A package ID specification did not match any workspace package
+    #[error("package ID specification `{spec}` did not match any packages")]
+    PackageNotFound { spec: String },
+
+    /// A cargo command building the dependencies failed
+    #[error("Failed to execute `{command}`")]
+    CommandFailed { command: String, status: ExitStatus },
+
+    /// Any other failure
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl SkeletonError {
+    /// Returns the `SkeletonError` in the chain of an error, if any,
+    /// other than [`SkeletonError::Other`].
+    pub fn find(err: &anyhow::Error) -> Option<&SkeletonError> {
+        err.chain()
+            .filter_map(|err| err.downcast_ref())
+            .find(|err| !matches!(err, SkeletonError::Other(_)))
+    }
+}
+
+/// Errors from within the crate keep their variant if they have one,
+/// without the context added on the way.
+impl From<anyhow::Error> for SkeletonError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast_ref::<SkeletonError>() {
+            None | Some(SkeletonError::Other(_)) => SkeletonError::Other(err),
+            Some(_) => err.downcast().expect("error was just found in the chain"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn finds_error_behind_context() {
+        let err = Err::<(), _>(SkeletonError::PackageNotFound { spec: "foo".into() })
+            .context("planning skeleton build")
+            .unwrap_err();
+
+        assert!(matches!(
+            SkeletonError::find(&err),
+            Some(SkeletonError::PackageNotFound { spec }) if spec == "foo"
+        ));
+        assert!(SkeletonError::find(&anyhow::anyhow!("other")).is_none());
+    }
+
+    #[test]
+    fn keeps_variant_of_wrapped_error() {
+        let err = Err::<(), _>(SkeletonError::PackageNotFound { spec: "foo".into() })
+            .context("planning skeleton build")
+            .unwrap_err();
+
+        assert!(matches!(
+            SkeletonError::from(err),
+            SkeletonError::PackageNotFound { spec } if spec == "foo"
+        ));
+        assert!(matches!(
+            SkeletonError::from(anyhow::anyhow!("other")),
+            SkeletonError::Other(_)
+        ));
+
+        let err = Err::<(), _>(SkeletonError::Other(anyhow::anyhow!("other")))
+            .context("unpacking skeleton archive")
+            .unwrap_err();
+
+        assert_eq!(
+            "unpacking skeleton archive: other",
+            format!("{:#}", SkeletonError::from(err))
+        );
+    }
+}
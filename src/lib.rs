@@ -1,12 +1,76 @@
-#[doc(hidden)]
-pub mod build;
-#[doc(hidden)]
-pub mod create;
-#[doc(hidden)]
-mod unpack;
+//! Create, read, and build skeletons of Cargo workspaces.
+//!
+//! A skeleton is a tar archive with the bare minimum of a workspace
+//! needed to compile its dependencies: manifests, lockfiles, config
+//! files, and stub targets. The archive only changes when one of
+//! those files does, which keeps the dependency build of a Docker
+//! image cached while the sources change.
+//!
+//! - [`SkeletonBuilder`] creates an archive from the workspace
+//!   metadata and writes it to any [`Write`](std::io::Write) sink.
+//! - [`SkeletonArchive`] reads the [`Lockfile`] and entries of an
+//!   archive, or unpacks it.
+//! - [`Workspace`] looks up the packages of a workspace by name or
+//!   ID, loaded from its metadata or `Skeleton.lock`.
+//! - [`plan_skeleton_build`] works out the cargo commands that build
+//!   the dependencies of an unpacked skeleton, without running them.
+//!
+//! ```no_run
+//! use cargo_skeleton::{plan_skeleton_build, BuildOptions, SkeletonArchive, SkeletonBuilder};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let metadata = cargo_metadata::MetadataCommand::new().exec()?;
+//!
+//! let mut archive = vec![];
+//! SkeletonBuilder::new(metadata).write(&mut archive)?;
+//!
+//! SkeletonArchive::new(&archive[..]).unpack("/tmp/skeleton")?;
+//!
+//! let plan = plan_skeleton_build(&BuildOptions::new("/tmp/skeleton/Cargo.toml"))?;
+//!
+//! for command in plan.commands.iter() {
+//!     println!("{}", command.to_shell());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Fallible functions return a [`SkeletonError`], with a variant for
+//! each failure callers may want to handle.
+//!
+//! Package IDs in the lockfile are absolute paths for workspace
+//! members, so a skeleton has to be unpacked at the same path as the
+//! workspace it was created from to be built.
+
+mod audit;
+mod build;
+mod cargo_lock;
+mod clean;
+mod complete;
+mod config;
+mod create;
+mod dockerfile;
+mod error;
 mod lockfile;
 mod package;
+mod secrets;
+mod settings;
+mod shell;
+mod stamp;
+mod unpack;
+mod verify;
 mod workspace;
 
 #[doc(hidden)]
 pub mod cli;
+
+pub use build::{
+    build_skeleton_archive, build_skeleton_package, build_skeleton_variants, plan_skeleton_build,
+    BuildCommand, BuildOptions, BuildPlan, BuildReport, BuildVariant, CargoCommand, CommandReport,
+};
+pub use create::{create_skeleton, CreateOptions, CreateReport, SkeletonBuilder, DEFAULT_OUT_PATH};
+pub use error::SkeletonError;
+pub use lockfile::{load_lockfile, Lockfile, LOCKFILE_NAME};
+pub use package::{Package, PackageId};
+pub use unpack::{unpack_skeleton_archive, SkeletonArchive, SkeletonContents, UnpackOptions};
+pub use workspace::Workspace;
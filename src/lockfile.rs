@@ -1,10 +1,10 @@
 use std::{fs::File, io::Read};
 
-use anyhow::{bail, Context, Result};
-use camino::Utf8Path;
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 use serde::{Deserialize, Serialize};
 
-use crate::package::Package;
+use crate::{error::SkeletonError, package::Package};
 
 /// The standard file name used for the lockfile on disk.
 pub const LOCKFILE_NAME: &str = "Skeleton.lock";
@@ -22,22 +22,40 @@ const HEADER_COMMENT: &str = r##"# This file is automatically @generated by Carg
 /// directory tree. The lockfile records the exact dependencies
 /// needed to build the package as resolved by Cargo.
 #[derive(Debug, Default, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Lockfile {
+    /// Paths of the target stubs in the skeleton, relative to the
+    /// workspace root
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stubs: Vec<Utf8PathBuf>,
+
     #[serde(rename = "package")]
     pub packages: Vec<Package>,
 }
 
 impl Lockfile {
-    // Serialize the Lockfile to a TOML string.
-    pub fn to_string(&mut self) -> String {
+    /// Parse a lockfile from its TOML contents.
+    pub fn parse(contents: &str) -> Result<Self, SkeletonError> {
+        let lockfile = toml::from_str(contents).map_err(SkeletonError::InvalidLockfile)?;
+
+        Ok(lockfile)
+    }
+
+    /// Sort the stubs, packages, and dependencies, so the serialized
+    /// lockfile does not depend on the order they were found in.
+    pub(crate) fn sort(&mut self) {
+        self.stubs.sort();
         self.packages.sort();
         for pkg in self.packages.iter_mut() {
             pkg.dependencies.sort();
         }
+    }
 
+    /// Serialize the lockfile to a TOML string.
+    pub(crate) fn to_toml(&self) -> String {
         let mut out = String::new();
 
-        out.push_str(&HEADER_COMMENT);
+        out.push_str(HEADER_COMMENT);
         out.push_str(&toml::to_string_pretty(&self).unwrap());
 
         out
@@ -45,15 +63,13 @@ impl Lockfile {
 }
 
 /// Load the workspace's lockfile from disk.
-pub fn load_lockfile(workspace_root: &Utf8Path) -> Result<Lockfile> {
+pub fn load_lockfile(workspace_root: &Utf8Path) -> Result<Lockfile, SkeletonError> {
     let path = workspace_root.join(LOCKFILE_NAME);
 
     if !path.exists() {
-        bail!(
-            "Failed to read {} from workspace directory {}",
-            LOCKFILE_NAME,
-            workspace_root
-        );
+        return Err(SkeletonError::MissingLockfile {
+            workspace_root: workspace_root.to_owned(),
+        });
     }
 
     let mut file = File::open(path).context("opening lockfile")?;
@@ -62,9 +78,7 @@ pub fn load_lockfile(workspace_root: &Utf8Path) -> Result<Lockfile> {
 
     file.read_to_string(&mut buf).context("reading lockfile")?;
 
-    let lockfile: Lockfile = toml::from_str(&buf).context("parsing lockfile")?;
-
-    Ok(lockfile)
+    Lockfile::parse(&buf)
 }
 
 #[cfg(test)]
@@ -72,31 +86,41 @@ mod tests {
     use super::*;
 
     #[test]
-    fn lockfile_to_string() {
+    fn lockfile_to_toml() {
         let mut lockfile = Lockfile {
+            stubs: vec!["src/main.rs".into(), "src/lib.rs".into()],
             packages: vec![
                 Package {
                     name: "foo".into(),
                     id: "file:///foo".into(),
                     dependencies: vec!["file:///bar".into(), "baz".into()],
+                    keep_source: false,
                 },
                 Package {
                     name: "bar".into(),
                     id: "file:///bar".into(),
                     dependencies: vec!["baz".into()],
+                    keep_source: true,
                 },
             ],
         };
 
-        let out = lockfile.to_string();
+        lockfile.sort();
+        let out = lockfile.to_toml();
 
         let expected = r##"# This file is automatically @generated by Cargo Skeleton.
 # It is not intended for manual editing.
 
+stubs = [
+    "src/lib.rs",
+    "src/main.rs",
+]
+
 [[package]]
 name = "bar"
 id = "file:///bar"
 dependencies = ["baz"]
+keep-source = true
 
 [[package]]
 name = "foo"
@@ -109,4 +133,24 @@ dependencies = [
 
         assert_eq!(expected, out);
     }
+
+    #[test]
+    fn load_missing_lockfile() {
+        let tmp_dir = tempfile::TempDir::with_prefix("cargo-skeleton").expect("creating temp dir");
+        let root = Utf8Path::from_path(tmp_dir.path()).expect("temp dir should be utf-8");
+
+        let err = load_lockfile(root).unwrap_err();
+
+        assert!(matches!(
+            err,
+            SkeletonError::MissingLockfile { workspace_root } if workspace_root == root
+        ));
+    }
+
+    #[test]
+    fn parse_invalid_lockfile() {
+        let err = Lockfile::parse("stubs = 1").unwrap_err();
+
+        assert!(matches!(err, SkeletonError::InvalidLockfile(_)));
+    }
 }
@@ -1,11 +1,29 @@
-use anyhow::Result;
-use cargo_skeleton::cli;
-use clap::Parser;
+use std::process::ExitCode;
 
-fn main() -> Result<()> {
-    env_logger::init();
+use cargo_skeleton::{cli, SkeletonError};
 
-    let args = cli::Cli::parse();
+fn main() -> ExitCode {
+    let args = cli::parse();
 
-    cli::run(args)
+    match cli::run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+/// Exit codes are documented in the `cargo skeleton --help` output.
+fn exit_code(err: &anyhow::Error) -> u8 {
+    match SkeletonError::find(err) {
+        Some(SkeletonError::MissingLockfile { .. }) => 3,
+        Some(SkeletonError::InvalidLockfile(_)) => 4,
+        Some(SkeletonError::MissingArchive { .. }) => 5,
+        Some(SkeletonError::ExistingWorkspace { .. }) => 6,
+        Some(SkeletonError::PackageNotFound { .. }) => 7,
+        Some(SkeletonError::CommandFailed { .. }) => 8,
+        _ => 1,
+    }
 }
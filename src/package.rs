@@ -6,8 +6,11 @@ use cargo_metadata::{
 };
 use serde::{Deserialize, Serialize};
 
+use crate::error::SkeletonError;
+
 /// Meta information for a local package and it's dependencies.
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Package {
     /// The name field as given in Cargo.toml
     pub name: String,
@@ -15,17 +18,25 @@ pub struct Package {
     pub id: PackageId,
     /// The IDs of the resolved direct dependencies of the package
     pub dependencies: Vec<PackageId>,
+    /// Whether the package source is kept in the skeleton, so it is
+    /// compiled along with the dependencies
+    #[serde(rename = "keep-source", default, skip_serializing_if = "is_false")]
+    pub keep_source: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
 }
 
 impl Package {
-    pub fn load_metadata_dependencies(&mut self, metadata: &Metadata) -> Result<()> {
+    pub fn load_metadata_dependencies(&mut self, metadata: &Metadata) -> Result<(), SkeletonError> {
         let deps: Vec<PackageId> = metadata
             .resolve
             .as_ref()
             .ok_or_else(|| anyhow!("Metadata missing deps"))?
             .nodes
             .iter()
-            .find(|node| &node.id.repr == self.id.as_str())
+            .find(|node| node.id.repr == self.id.as_str())
             .ok_or_else(|| anyhow!("Missing package resolution for {}", self.id))?
             .deps
             .iter()
@@ -49,6 +60,7 @@ impl From<&MetaPackage> for Package {
             name: package.name.clone(),
             id: package.id.clone().into(),
             dependencies: vec![],
+            keep_source: false,
         }
     }
 }
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{Metadata, MetadataCommand};
+use log::*;
+use serde::Deserialize;
+
+use crate::{build::CargoCommand, unpack::SkeletonArchive};
+
+/// Defaults read from `[workspace.metadata.skeleton]` in the root
+/// manifest. Options given on the command line take precedence.
+///
+/// ```toml
+/// [workspace.metadata.skeleton]
+/// out-path = "skeleton.tar"
+/// exclude = ["xtask"]
+/// keep-source = ["proto-types"]
+/// include-files = [".sqlx/*.json"]
+/// scrub-secrets = true
+///
+/// [workspace.metadata.skeleton.build]
+/// packages = ["server"]
+/// cargo-command = "build"
+/// args = ["--release", "--locked"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    /// Path of the skeleton archive
+    pub out_path: Option<Utf8PathBuf>,
+
+    /// Features to activate when resolving dependencies
+    pub features: Vec<String>,
+
+    /// Activate all features when resolving dependencies
+    pub all_features: bool,
+
+    /// Do not activate the `default` feature when resolving dependencies
+    pub no_default_features: bool,
+
+    /// Packages to create a scoped skeleton for
+    pub packages: Vec<String>,
+
+    /// Packages to leave out of the skeleton
+    pub exclude: Vec<String>,
+
+    /// Packages to keep the complete source of
+    pub keep_source: Vec<String>,
+
+    /// Strip secrets from config files instead of failing
+    pub scrub_secrets: bool,
+
+    /// Globs matching additional files to add as-is
+    pub include_files: Vec<String>,
+
+    /// Globs matching files to leave out of the archive
+    pub exclude_files: Vec<String>,
+
+    /// Check that the written archive resolves offline
+    pub verify: bool,
+
+    /// Defaults for `cargo skeleton build`
+    pub build: BuildSettings,
+}
+
+/// Defaults for `cargo skeleton build`, read from
+/// `[workspace.metadata.skeleton.build]`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildSettings {
+    /// Packages to build dependencies for
+    pub packages: Vec<String>,
+
+    /// Packages to exclude from the build
+    pub exclude: Vec<String>,
+
+    /// The cargo command to compile the dependencies with
+    pub cargo_command: Option<CargoCommand>,
+
+    /// Program to run instead of cargo
+    pub wrapper: Option<String>,
+
+    /// Subcommand words to use instead of the cargo command
+    pub subcommand: Vec<String>,
+
+    /// Profiles to build the dependencies for
+    pub profiles: Vec<String>,
+
+    /// Target triples to build the dependencies for
+    pub targets: Vec<String>,
+
+    /// Additional cargo build args
+    pub args: Vec<String>,
+}
+
+impl Settings {
+    /// Read the settings from workspace metadata.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let value = &metadata.workspace_metadata["skeleton"];
+
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+
+        serde_json::from_value(value.clone())
+            .context("parsing [workspace.metadata.skeleton] settings")
+    }
+
+    /// Read the settings from the root manifest in a skeleton archive,
+    /// before it is unpacked.
+    pub fn from_archive(archive_path: &Utf8Path) -> Result<Self> {
+        let contents = match SkeletonArchive::open(archive_path)?.read_file("Cargo.toml")? {
+            Some(contents) => contents,
+            None => return Ok(Self::default()),
+        };
+
+        Self::from_manifest(&contents)
+    }
+
+    /// Read the settings from the contents of a root manifest.
+    fn from_manifest(contents: &str) -> Result<Self> {
+        let manifest: toml::Table = toml::from_str(contents).context("parsing root manifest")?;
+
+        let value = manifest
+            .get("workspace")
+            .and_then(|workspace| workspace.get("metadata"))
+            .and_then(|metadata| metadata.get("skeleton"));
+
+        match value {
+            Some(value) => value
+                .clone()
+                .try_into()
+                .context("parsing [workspace.metadata.skeleton] settings"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Read the settings for the workspace the manifest belongs to.
+    ///
+    /// If the manifest does not exist, e.g. because the skeleton has
+    /// not been unpacked yet, the default settings are returned.
+    pub fn load(manifest_path: &Utf8Path) -> Result<Self> {
+        if !manifest_path.exists() {
+            debug!("No manifest at {}, using default settings", manifest_path);
+            return Ok(Self::default());
+        }
+
+        let metadata = MetadataCommand::new()
+            .manifest_path(manifest_path)
+            .no_deps()
+            .exec()
+            .context("executing cargo metadata")?;
+
+        Self::from_metadata(&metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings() {
+        let value = serde_json::json!({
+            "out-path": "target/skeleton.tar",
+            "keep-source": ["proto-types"],
+            "build": {
+                "packages": ["server"],
+                "cargo-command": "clippy",
+                "args": ["--release"],
+            },
+        });
+
+        let settings: Settings = serde_json::from_value(value).unwrap();
+
+        assert_eq!(Some("target/skeleton.tar".into()), settings.out_path);
+        assert_eq!(vec!["proto-types"], settings.keep_source);
+        assert!(!settings.scrub_secrets);
+        assert_eq!(vec!["server"], settings.build.packages);
+        assert_eq!(Some(CargoCommand::Clippy), settings.build.cargo_command);
+        assert_eq!(vec!["--release"], settings.build.args);
+    }
+
+    #[test]
+    fn parses_manifest_settings() {
+        let settings = Settings::from_manifest(
+            r#"
+            [workspace]
+            members = ["server"]
+
+            [workspace.metadata.skeleton.build]
+            packages = ["server"]
+            args = ["--locked"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(vec!["server"], settings.build.packages);
+        assert_eq!(vec!["--locked"], settings.build.args);
+
+        let settings = Settings::from_manifest("[package]\nname = \"app\"\n").unwrap();
+
+        assert!(settings.build.packages.is_empty());
+    }
+}
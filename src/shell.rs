@@ -0,0 +1,248 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use anstyle::Style;
+use camino::Utf8PathBuf;
+use clap::ColorChoice;
+use serde::Serialize;
+
+use crate::build::{BuildPlan, BuildVariant};
+
+/// The format of the messages printed by the command line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Styled status lines
+    #[default]
+    Human,
+    /// One JSON event per line
+    Json,
+}
+
+/// A machine-readable event, printed as a line of JSON with
+/// `--message-format json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// A package was added to the skeleton
+    PackageSelected { name: &'a str },
+    /// A file was added to the archive
+    EntryAdded { path: &'a Utf8PathBuf },
+    /// The skeleton archive was written
+    ArchiveCreated {
+        path: &'a Utf8PathBuf,
+        size: u64,
+        digest: &'a str,
+        packages: usize,
+        entries: usize,
+        duration_ms: u128,
+    },
+    /// The skeleton archive was unpacked
+    ArchiveUnpacked {
+        path: &'a Utf8PathBuf,
+        dest: &'a Utf8PathBuf,
+        duration_ms: u128,
+    },
+    /// The commands a build would run
+    BuildPlan {
+        #[serde(flatten)]
+        plan: &'a BuildPlan,
+    },
+    /// A cargo command finished
+    CommandFinished {
+        package: &'a str,
+        #[serde(flatten)]
+        variant: &'a BuildVariant,
+        command: &'a str,
+        duration_ms: u128,
+        success: bool,
+    },
+    /// The dependencies for a profile and target were built, or were
+    /// already up to date
+    VariantFinished {
+        #[serde(flatten)]
+        variant: &'a BuildVariant,
+        status: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A build finished
+    BuildFinished {
+        fresh: usize,
+        built: usize,
+        failed: usize,
+        duration_ms: u128,
+    },
+    /// A file was, or would be, removed
+    FileRemoved {
+        path: &'a Utf8PathBuf,
+        dry_run: bool,
+    },
+    /// Cleaning finished
+    CleanFinished { removed: usize, dry_run: bool },
+    /// A dependency artifact was checked
+    DependencyAudited { package: &'a str, fresh: bool },
+    /// An audit finished
+    AuditFinished { fresh: usize, rebuilt: usize },
+    /// A generated file was checked, or written if out of date
+    FileGenerated {
+        path: &'a Utf8PathBuf,
+        fresh: bool,
+        check: bool,
+    },
+    /// Workspace sources were verified
+    SourcesVerified { targets: usize },
+    /// The command failed
+    Error { message: String },
+}
+
+/// How much the command line prints.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Only errors
+    Quiet,
+    /// Status lines and errors
+    #[default]
+    Normal,
+    /// Status lines and log messages, up to `-vvv`
+    Verbose(u8),
+}
+
+impl Verbosity {
+    /// Returns the default log filter for the verbosity, used unless
+    /// `RUST_LOG` is set.
+    pub fn log_filter(&self) -> &'static str {
+        match self {
+            Self::Quiet | Self::Normal => "error",
+            Self::Verbose(1) => "info",
+            Self::Verbose(2) => "debug",
+            Self::Verbose(_) => "trace",
+        }
+    }
+
+    /// Returns the cargo flags with the same verbosity.
+    pub fn cargo_args(&self) -> Vec<String> {
+        match self {
+            Self::Quiet => vec!["--quiet".to_string()],
+            Self::Normal => vec![],
+            Self::Verbose(count) => vec![format!("-{}", "v".repeat(*count.min(&2) as usize))],
+        }
+    }
+}
+
+/// Prints the output of the command line in the selected format.
+#[derive(Debug, Default)]
+pub struct Shell {
+    message_format: MessageFormat,
+    verbosity: Verbosity,
+    color: ColorChoice,
+}
+
+impl Shell {
+    pub fn new(message_format: MessageFormat, verbosity: Verbosity, color: ColorChoice) -> Self {
+        Self {
+            message_format,
+            verbosity,
+            color,
+        }
+    }
+
+    /// Set up logging and colored output. `RUST_LOG` takes precedence
+    /// over the verbosity.
+    pub fn init(&self) {
+        let (choice, style) = match self.color {
+            ColorChoice::Auto => (anstream::ColorChoice::Auto, env_logger::WriteStyle::Auto),
+            ColorChoice::Always => (
+                anstream::ColorChoice::Always,
+                env_logger::WriteStyle::Always,
+            ),
+            ColorChoice::Never => (anstream::ColorChoice::Never, env_logger::WriteStyle::Never),
+        };
+
+        choice.write_global();
+
+        let env = env_logger::Env::default().default_filter_or(self.verbosity.log_filter());
+        let _ = env_logger::Builder::from_env(env)
+            .write_style(style)
+            .try_init();
+    }
+
+    /// Returns the cargo args with the same verbosity and color.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = self.verbosity.cargo_args();
+
+        match self.color {
+            ColorChoice::Auto => {}
+            ColorChoice::Always => args.extend(["--color".to_string(), "always".to_string()]),
+            ColorChoice::Never => args.extend(["--color".to_string(), "never".to_string()]),
+        }
+
+        args
+    }
+
+    /// Print a styled status line, unless printing JSON or quiet.
+    pub fn status(&self, style: Style, verb: &str, message: impl Display) {
+        if self.message_format != MessageFormat::Human || self.verbosity == Verbosity::Quiet {
+            return;
+        }
+
+        // Output may be piped into a closed reader, e.g. `head`.
+        let message = message.to_string();
+        let _ = if message.is_empty() {
+            writeln!(anstream::stdout(), "{style}{verb}{style:#}")
+        } else {
+            writeln!(anstream::stdout(), "{style}{verb}{style:#} {message}")
+        };
+    }
+
+    /// Print an event, if printing JSON.
+    pub fn event(&self, event: Event) {
+        if self.message_format != MessageFormat::Json {
+            return;
+        }
+
+        match serde_json::to_string(&event) {
+            Ok(json) => {
+                let _ = writeln!(io::stdout(), "{}", json);
+            }
+            Err(err) => log::warn!("Failed to serialize event: {}", err),
+        }
+    }
+
+    /// Returns true if printing JSON.
+    pub fn is_json(&self) -> bool {
+        self.message_format == MessageFormat::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_verbosity() {
+        assert_eq!(vec!["--quiet"], Verbosity::Quiet.cargo_args());
+        assert!(Verbosity::Normal.cargo_args().is_empty());
+        assert_eq!(vec!["-vv"], Verbosity::Verbose(3).cargo_args());
+        assert_eq!("debug", Verbosity::Verbose(2).log_filter());
+    }
+
+    #[test]
+    fn serializes_events() {
+        let variant = BuildVariant {
+            profile: Some("release".into()),
+            target: None,
+        };
+        let event = Event::VariantFinished {
+            variant: &variant,
+            status: "built",
+            error: None,
+        };
+
+        assert_eq!(
+            r#"{"reason":"variant-finished","profile":"release","target":null,"status":"built"}"#,
+            serde_json::to_string(&event).unwrap()
+        );
+    }
+}
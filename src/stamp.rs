@@ -0,0 +1,495 @@
+use std::{env, fs, process::Command};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    build::{BuildCommand, BuildVariant},
+    config::{discover_config_files, relative_path},
+    create::walk_package_files,
+    lockfile::{load_lockfile, LOCKFILE_NAME},
+    verify::VCS_DIRS,
+};
+
+/// The standard file name used for the build stamp in the target
+/// directory.
+pub const STAMP_NAME: &str = "skeleton-build.json";
+
+/// Environment variables that change how dependencies are compiled.
+const BUILD_ENV_VARS: &[&str] = &[
+    "RUSTFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "RUSTDOCFLAGS",
+    "CARGO_ENCODED_RUSTDOCFLAGS",
+];
+
+/// Prefixes of environment variables that override Cargo config
+/// affecting compilation.
+const BUILD_ENV_PREFIXES: &[&str] = &["CARGO_PROFILE_", "CARGO_BUILD_", "CARGO_TARGET_"];
+
+/// Records the dependency builds that finished in a target directory,
+/// so repeated builds with the same inputs can be skipped.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildStamp {
+    #[serde(rename = "build")]
+    pub builds: Vec<StampEntry>,
+}
+
+/// A finished dependency build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StampEntry {
+    /// Digest of the build inputs, commands, and toolchain
+    pub digest: String,
+
+    /// The profile and target that were built
+    #[serde(flatten)]
+    pub variant: BuildVariant,
+
+    /// The commands that were run, formatted for a shell
+    pub commands: Vec<String>,
+
+    /// Output of `rustc -vV`
+    pub toolchain: String,
+}
+
+/// The inputs of a dependency build besides its commands and the
+/// toolchain.
+#[derive(Debug, Default)]
+pub struct StampInputs {
+    /// Contents of `Skeleton.lock`, the manifests, `Cargo.lock`, the
+    /// config files, and the sources of members with kept source in
+    /// the workspace, by path relative to the workspace root
+    pub files: Vec<(Utf8PathBuf, Vec<u8>)>,
+
+    /// Environment variables that change how dependencies are compiled
+    pub env: Vec<(String, String)>,
+}
+
+impl StampInputs {
+    /// Read the build inputs of a workspace.
+    ///
+    /// Every `Cargo.toml` and `Cargo.lock` in the workspace is read,
+    /// except in the target directory and version control directories,
+    /// along with the config files Cargo would read from the member
+    /// directories. Members with kept source are compiled with the
+    /// dependencies, so their source files are read too.
+    pub fn load(root: &Utf8Path, target_dir: &Utf8Path) -> Result<Self> {
+        let lockfile = load_lockfile(root)?;
+        let kept: Vec<&str> = lockfile
+            .packages
+            .iter()
+            .filter(|pkg| pkg.keep_source)
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+
+        let mut paths = vec![root.join(LOCKFILE_NAME)];
+        find_manifests(root, target_dir, &mut paths)?;
+
+        let member_dirs: Vec<Utf8PathBuf> = paths
+            .iter()
+            .filter(|path| path.file_name() == Some("Cargo.toml"))
+            .filter_map(|path| path.parent().map(Utf8Path::to_path_buf))
+            .collect();
+
+        for dir in member_dirs.iter() {
+            if package_name(dir)?.is_some_and(|name| kept.contains(&name.as_str())) {
+                paths.extend(
+                    walk_package_files(root, dir)?
+                        .into_iter()
+                        .map(|path| root.join(path)),
+                );
+            }
+        }
+
+        paths.extend(
+            discover_config_files(root, member_dirs.iter().map(Utf8PathBuf::as_path))?
+                .into_iter()
+                .map(|config| root.join(config.path)),
+        );
+
+        let mut files = paths
+            .into_iter()
+            .map(|path| -> Result<(Utf8PathBuf, Vec<u8>)> {
+                let contents = fs::read(&path).context(format!("reading {}", path))?;
+
+                Ok((relative_path(root, &path), contents))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        files.sort();
+        files.dedup();
+
+        Ok(Self {
+            files,
+            env: build_env(env::vars()),
+        })
+    }
+}
+
+/// Returns the variables that change how dependencies are compiled,
+/// sorted by name.
+fn build_env(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = vars
+        .filter(|(name, _)| {
+            BUILD_ENV_VARS.contains(&name.as_str())
+                || BUILD_ENV_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .collect();
+
+    vars.sort();
+    vars
+}
+
+/// Returns the package name of the manifest in a directory, if it
+/// has a `[package]` table.
+fn package_name(dir: &Utf8Path) -> Result<Option<String>> {
+    let manifest_path = dir.join("Cargo.toml");
+    let manifest: toml::Table = fs::read_to_string(&manifest_path)
+        .context(format!("reading {}", manifest_path))?
+        .parse()
+        .context(format!("parsing {}", manifest_path))?;
+
+    Ok(manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_str())
+        .map(String::from))
+}
+
+/// Find the `Cargo.toml` and `Cargo.lock` files within a directory,
+/// recursively, skipping the target directory and version control
+/// directories.
+fn find_manifests(
+    dir: &Utf8Path,
+    target_dir: &Utf8Path,
+    paths: &mut Vec<Utf8PathBuf>,
+) -> Result<()> {
+    for entry in dir
+        .read_dir_utf8()
+        .context(format!("reading directory {}", dir))?
+    {
+        let entry = entry.context(format!("reading directory {}", dir))?;
+        let path = entry.path();
+
+        if path.is_dir() {
+            if path != target_dir && !VCS_DIRS.contains(&entry.file_name()) {
+                find_manifests(path, target_dir, paths)?;
+            }
+        } else if matches!(entry.file_name(), "Cargo.toml" | "Cargo.lock") {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+impl StampEntry {
+    /// Create an entry for the commands building a variant.
+    ///
+    /// The digest covers the build inputs, every command line, and
+    /// the toolchain version, so a change to any of them results in
+    /// a new digest. Args that only change cargo's output are left
+    /// out.
+    pub fn new<'a>(
+        inputs: &StampInputs,
+        toolchain: &str,
+        variant: &BuildVariant,
+        commands: impl Iterator<Item = &'a BuildCommand>,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        for (path, contents) in inputs.files.iter() {
+            hasher.update(path.as_str().as_bytes());
+            hasher.update([0]);
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(contents);
+        }
+        for (name, value) in inputs.env.iter() {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(toolchain.as_bytes());
+
+        let mut lines = vec![];
+        for command in commands {
+            for arg in Some(&command.program)
+                .into_iter()
+                .chain(command.args.iter())
+            {
+                hasher.update([0]);
+                hasher.update(arg.as_bytes());
+            }
+            lines.push(command.to_shell());
+        }
+
+        Self {
+            digest: format!("{:x}", hasher.finalize()),
+            variant: variant.clone(),
+            commands: lines,
+            toolchain: toolchain.to_string(),
+        }
+    }
+}
+
+impl BuildStamp {
+    /// Load the stamp from a target directory. A missing or unreadable
+    /// stamp is treated as empty.
+    pub fn load(target_dir: &Utf8Path) -> Self {
+        let path = target_dir.join(STAMP_NAME);
+
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+
+        serde_json::from_str(&data).unwrap_or_else(|err| {
+            warn!("Ignoring invalid build stamp {}: {}", path, err);
+            Self::default()
+        })
+    }
+
+    /// Write the stamp to a target directory.
+    pub fn save(&self, target_dir: &Utf8Path) -> Result<()> {
+        fs::create_dir_all(target_dir).context("creating target directory")?;
+
+        let data = serde_json::to_string_pretty(self).context("serializing build stamp")?;
+
+        fs::write(target_dir.join(STAMP_NAME), data).context("writing build stamp")
+    }
+
+    /// Returns true if a build with the digest finished.
+    pub fn contains(&self, digest: &str) -> bool {
+        self.builds.iter().any(|entry| entry.digest == digest)
+    }
+
+    /// Record a finished build, replacing the previous build of the
+    /// same variant.
+    pub fn record(&mut self, entry: StampEntry) {
+        self.builds.retain(|other| other.variant != entry.variant);
+        self.builds.push(entry);
+    }
+}
+
+/// Returns the version of the toolchain used in the workspace, as
+/// reported by `rustc -vV`.
+pub fn toolchain_version(workspace_root: &Utf8Path) -> Result<String> {
+    let rustc = std::env::var("RUSTC").unwrap_or("rustc".into());
+
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .current_dir(workspace_root)
+        .output()
+        .context("executing `rustc -vV`")?;
+
+    if !output.status.success() {
+        anyhow::bail!("Failed to execute `rustc -vV`");
+    }
+
+    String::from_utf8(output.stdout).context("rustc version should be utf-8")
+}
+
+/// Returns the target directory given with `--target-dir` in the
+/// cargo args, if any.
+pub fn target_dir_arg(args: &[String]) -> Option<Utf8PathBuf> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--target-dir" {
+            return args.next().map(Utf8PathBuf::from);
+        }
+        if let Some(dir) = arg.strip_prefix("--target-dir=") {
+            return Some(dir.into());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_target_dir_arg() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+
+        assert_eq!(None, target_dir_arg(&args(&["--release"])));
+        assert_eq!(
+            Some("out".into()),
+            target_dir_arg(&args(&["--release", "--target-dir", "out"]))
+        );
+        assert_eq!(
+            Some("out".into()),
+            target_dir_arg(&args(&["--target-dir=out"]))
+        );
+    }
+
+    #[test]
+    fn records_one_build_per_variant() {
+        let variant = BuildVariant::default();
+        let mut stamp = BuildStamp::default();
+
+        let inputs = StampInputs::default();
+        let entry = |toolchain| StampEntry::new(&inputs, toolchain, &variant, std::iter::empty());
+
+        stamp.record(entry("a"));
+        stamp.record(entry("b"));
+
+        assert_eq!(1, stamp.builds.len());
+        assert!(stamp.contains(&entry("b").digest));
+        assert!(!stamp.contains(&entry("a").digest));
+    }
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn digest(root: &Utf8Path) -> String {
+        let inputs = StampInputs::load(root, &root.join("target")).unwrap();
+
+        StampEntry::new(
+            &inputs,
+            "rustc",
+            &BuildVariant::default(),
+            std::iter::empty(),
+        )
+        .digest
+    }
+
+    #[test]
+    fn loads_build_inputs() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", "package = []\n");
+        write(root, "Cargo.toml", "[workspace]\nmembers = [\"app\"]\n");
+        write(root, "Cargo.lock", "");
+        write(root, "app/Cargo.toml", "[package]\nname = \"app\"\n");
+        write(root, "app/.cargo/config.toml", "[build]\njobs = 1\n");
+        write(root, "app/src/main.rs", "");
+        write(root, "target/package/app/Cargo.toml", "");
+        write(root, ".git/Cargo.toml", "");
+
+        let inputs = StampInputs::load(root, &root.join("target")).unwrap();
+        let paths: Vec<&str> = inputs.files.iter().map(|(path, _)| path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            [
+                "Cargo.lock",
+                "Cargo.toml",
+                "Skeleton.lock",
+                "app/.cargo/config.toml",
+                "app/Cargo.toml"
+            ]
+        );
+    }
+
+    #[test]
+    fn feature_change_invalidates_stamp() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", "package = []\n");
+        write(
+            root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\n\n[dependencies]\nserde = \"1\"\n",
+        );
+        let before = digest(root);
+
+        write(
+            root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\n\n[dependencies]\nserde = { version = \"1\", features = [\"derive\"] }\n",
+        );
+
+        assert_ne!(before, digest(root));
+    }
+
+    #[test]
+    fn profile_change_invalidates_stamp() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(root, "Skeleton.lock", "package = []\n");
+        write(root, "Cargo.toml", "[package]\nname = \"app\"\n");
+        let before = digest(root);
+
+        write(
+            root,
+            ".cargo/config.toml",
+            "[profile.release]\nopt-level = \"s\"\n",
+        );
+        let configured = digest(root);
+
+        assert_ne!(before, configured);
+
+        let env = |vars: &[(&str, &str)]| StampInputs {
+            env: build_env(
+                vars.iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            ),
+            ..Default::default()
+        };
+        let env_digest = |vars| {
+            StampEntry::new(
+                &env(vars),
+                "rustc",
+                &BuildVariant::default(),
+                std::iter::empty(),
+            )
+            .digest
+        };
+
+        assert_eq!(env_digest(&[]), env_digest(&[("HOME", "/root")]));
+        assert_ne!(
+            env_digest(&[]),
+            env_digest(&[("CARGO_PROFILE_RELEASE_LTO", "true")])
+        );
+        assert_ne!(
+            env_digest(&[]),
+            env_digest(&[("RUSTFLAGS", "-Ctarget-cpu=native")])
+        );
+    }
+
+    #[test]
+    fn kept_source_change_invalidates_stamp() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Skeleton.lock",
+            "[[package]]\nname = \"proto\"\nid = \"proto\"\ndependencies = []\nkeep-source = true\n\n\
+             [[package]]\nname = \"app\"\nid = \"app\"\ndependencies = []\n",
+        );
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"proto\"]\n",
+        );
+        write(root, "app/Cargo.toml", "[package]\nname = \"app\"\n");
+        write(root, "app/src/main.rs", "fn main() {}");
+        write(root, "proto/Cargo.toml", "[package]\nname = \"proto\"\n");
+        write(root, "proto/src/lib.rs", "mod gen;");
+        write(root, "proto/src/gen.rs", "pub struct Message;");
+        let before = digest(root);
+
+        // Stubbed members are replaced by the real build anyway.
+        write(root, "app/src/main.rs", "fn main() { run() }");
+        assert_eq!(before, digest(root));
+
+        write(root, "proto/src/gen.rs", "pub struct Message(u32);");
+        assert_ne!(before, digest(root));
+    }
+}
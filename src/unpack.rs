@@ -1,36 +1,166 @@
-use std::{env, fs::File};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+};
 
-use anyhow::{bail, Context, Result};
-use camino::Utf8PathBuf;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 use tar::Archive;
 
-use crate::create::DEFAULT_OUT_PATH;
+use crate::{
+    create::DEFAULT_OUT_PATH,
+    error::SkeletonError,
+    lockfile::{Lockfile, LOCKFILE_NAME},
+    workspace::find_root,
+};
 
+/// Options for [`unpack_skeleton_archive`].
+///
+/// Fields may be added in future releases, so the options are created
+/// with [`UnpackOptions::new`] or `Default` and then changed.
 #[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct UnpackOptions {
+    /// Path of the skeleton archive [default: skeleton.tar]
     pub archive_path: Option<Utf8PathBuf>,
+
+    /// Directory to unpack the archive into [default: current directory]
     pub dest_path: Option<Utf8PathBuf>,
 }
 
-pub fn unpack_skeleton_archive(opts: UnpackOptions) -> Result<()> {
+impl UnpackOptions {
+    /// Create options unpacking an archive into a directory.
+    pub fn new(archive_path: impl Into<Utf8PathBuf>, dest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            archive_path: Some(archive_path.into()),
+            dest_path: Some(dest_path.into()),
+        }
+    }
+}
+
+/// Unpack a skeleton archive, failing if the destination contains a
+/// workspace that is not a skeleton.
+pub fn unpack_skeleton_archive(opts: UnpackOptions) -> Result<(), SkeletonError> {
     let archive_path = opts.archive_path.unwrap_or_else(|| DEFAULT_OUT_PATH.into());
-    let dest_path = opts.dest_path.unwrap_or_else(|| {
-        env::current_dir()
-            .expect("getting current dir")
-            .try_into()
-            .expect("current path should be utf-8")
-    });
+    let dest_path = match opts.dest_path {
+        Some(path) => path,
+        None => find_root(None)?,
+    };
 
-    let file = File::open(archive_path).context("opening archive file")?;
+    SkeletonArchive::open(archive_path)?.unpack(dest_path)
+}
+
+/// Reads a skeleton archive.
+///
+/// ```no_run
+/// use cargo_skeleton::SkeletonArchive;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let contents = SkeletonArchive::open("skeleton.tar")?.contents()?;
+///
+/// for package in contents.lockfile.packages.iter() {
+///     println!("{}", package.name);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SkeletonArchive<R: Read> {
+    archive: Archive<R>,
+}
 
-    let mut ar = Archive::new(file);
+/// The contents of a skeleton archive.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SkeletonContents {
+    /// The `Skeleton.lock` of the skeleton
+    pub lockfile: Lockfile,
 
-    if dest_path.join("Cargo.toml").exists() &&
-        !dest_path.join("Skeleton.lock").exists() {
-            bail!("Attempted to unpack a skeleton archive into an existing workspace");
+    /// Paths of the archive entries, in archive order
+    pub entries: Vec<Utf8PathBuf>,
+}
+
+impl SkeletonArchive<BufReader<File>> {
+    /// Open an archive file.
+    pub fn open(path: impl AsRef<Utf8Path>) -> Result<Self, SkeletonError> {
+        let path = path.as_ref();
+
+        let file = match File::open(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(SkeletonError::MissingArchive { path: path.to_owned() });
+            }
+            file => file.context("opening archive file")?,
+        };
+
+        Ok(Self::new(BufReader::new(file)))
     }
+}
+
+impl<R: Read> SkeletonArchive<R> {
+    /// Read an archive from a source.
+    pub fn new(reader: R) -> Self {
+        Self {
+            archive: Archive::new(reader),
+        }
+    }
+
+    /// Read the lockfile and the entry paths of the archive.
+    pub fn contents(mut self) -> Result<SkeletonContents, SkeletonError> {
+        let mut lockfile = None;
+        let mut entries = vec![];
 
-    ar.unpack(dest_path).context("unpacking archive")?;
+        for entry in self.archive.entries().context("reading archive")? {
+            let mut entry = entry.context("reading archive entry")?;
+            let path = entry.path().context("reading archive entry path")?;
+            let path = Utf8PathBuf::try_from(path.into_owned())
+                .context("archive path should be utf-8")?;
 
-    Ok(())
+            if path == LOCKFILE_NAME {
+                let mut buf = String::new();
+                entry.read_to_string(&mut buf).context("reading lockfile")?;
+                lockfile = Some(Lockfile::parse(&buf)?);
+            }
+
+            entries.push(path);
+        }
+
+        let Some(lockfile) = lockfile else {
+            return Err(anyhow!("Archive does not contain a {}", LOCKFILE_NAME).into());
+        };
+
+        Ok(SkeletonContents { lockfile, entries })
+    }
+
+    /// Read a file from the archive, if it has one at the path.
+    pub(crate) fn read_file(mut self, path: impl AsRef<Utf8Path>) -> Result<Option<String>> {
+        for entry in self.archive.entries().context("reading archive")? {
+            let mut entry = entry.context("reading archive entry")?;
+
+            if entry.path()?.as_ref() == path.as_ref().as_std_path() {
+                let mut buf = String::new();
+                entry
+                    .read_to_string(&mut buf)
+                    .context(format!("reading {} from archive", path.as_ref()))?;
+
+                return Ok(Some(buf));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Unpack the archive into a directory. Fails if the directory
+    /// already contains a workspace other than a skeleton.
+    pub fn unpack(mut self, dest_path: impl AsRef<Utf8Path>) -> Result<(), SkeletonError> {
+        let dest_path = dest_path.as_ref();
+
+        if dest_path.join("Cargo.toml").exists() && !dest_path.join(LOCKFILE_NAME).exists() {
+            return Err(SkeletonError::ExistingWorkspace {
+                dest_path: dest_path.to_owned(),
+            });
+        }
+
+        self.archive.unpack(dest_path).context("unpacking archive")?;
+
+        Ok(())
+    }
 }
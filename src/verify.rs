@@ -0,0 +1,388 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Read,
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{CargoOpt, DependencyKind, Metadata, MetadataCommand};
+use log::*;
+use tar::Archive;
+use tempfile::TempDir;
+
+use crate::{
+    clean::{fingerprint_dirs, profile_dirs},
+    create::is_stub,
+    lockfile::Lockfile,
+};
+
+/// Directories version control systems keep their data in.
+pub(crate) const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".jj", ".pijul"];
+
+#[derive(Debug, Default)]
+pub struct VerifySourcesOptions {
+    /// Path to the root manifest of the workspace
+    pub manifest_path: Option<Utf8PathBuf>,
+
+    /// Also check that member artifacts are newer than their sources
+    pub check_artifacts: bool,
+
+    /// Path to the target directory
+    pub target_dir: Option<Utf8PathBuf>,
+}
+
+/// Verify that every workspace target has its real source in place.
+///
+/// Fails if any target source file is still a skeleton stub. With
+/// `check_artifacts`, also fails if the newest fingerprint Cargo
+/// recorded for a member is older than the member's sources, which
+/// means the artifacts were built from something else. Returns the
+/// number of targets checked.
+pub fn verify_sources(opts: VerifySourcesOptions) -> Result<usize> {
+    let mut metadata = MetadataCommand::new();
+    if let Some(path) = &opts.manifest_path {
+        metadata.manifest_path(path);
+    }
+    let metadata = metadata
+        .no_deps()
+        .exec()
+        .context("executing cargo metadata")?;
+
+    let members: Vec<_> = metadata.workspace_packages();
+
+    let mut stubs = vec![];
+    let mut checked = 0;
+
+    for package in members.iter() {
+        for target in package.targets.iter() {
+            checked += 1;
+
+            let contents = fs::read_to_string(&target.src_path)
+                .context(format!("reading target source: {}", target.src_path))?;
+
+            if is_stub(&contents) {
+                stubs.push(target.src_path.clone());
+            }
+        }
+    }
+
+    if !stubs.is_empty() {
+        bail!(
+            "Skeleton stubs are still in place of the real sources: {}",
+            stubs
+                .iter()
+                .map(|path| path.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if !opts.check_artifacts {
+        return Ok(checked);
+    }
+
+    let target_dir = opts
+        .target_dir
+        .unwrap_or_else(|| metadata.target_directory.clone());
+    let profile_dirs = profile_dirs(&target_dir);
+
+    let mut stale = vec![];
+
+    for package in members.iter() {
+        let package_root = package
+            .manifest_path
+            .parent()
+            .expect("manifest has a parent directory");
+        let skip_dirs = [target_dir.as_path()];
+
+        let source_mtime = package
+            .targets
+            .iter()
+            .map(|target| target_source_mtime(package_root, &target.src_path, &skip_dirs))
+            .chain(Some(mtime(&package.manifest_path)))
+            .flatten()
+            .max();
+
+        let artifact_mtime = profile_dirs
+            .iter()
+            .flat_map(|dir| fingerprint_dirs(dir, &package.name))
+            .filter_map(|dir| newest_mtime(&dir, &[]))
+            .max();
+
+        match (source_mtime, artifact_mtime) {
+            (_, None) => warn!("No artifacts found for package {}", package.name),
+            (Some(source), Some(artifact)) if artifact < source => {
+                stale.push(package.name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    if !stale.is_empty() {
+        bail!(
+            "Artifacts are older than the real sources for packages: {}",
+            stale.join(", ")
+        );
+    }
+
+    Ok(checked)
+}
+
+/// Check that a freshly written skeleton archive resolves on its own.
+///
+/// The archive is unpacked into a temporary directory and resolved
+/// with `cargo metadata --locked --offline`. The dependencies of every
+/// package in the lockfile must match the ones resolved for the
+/// original workspace. Path packages are compared by their location
+/// relative to the workspace root, since the roots differ.
+pub(crate) fn verify_skeleton_archive(
+    archive: impl Read,
+    metadata: &Metadata,
+    lockfile: &Lockfile,
+    features: &[CargoOpt],
+) -> Result<()> {
+    let tmp_dir = TempDir::with_prefix("cargo-skeleton").context("creating temp dir")?;
+    let tmp_root: &Utf8Path = tmp_dir
+        .path()
+        .try_into()
+        .context("temp dir path should be utf-8")?;
+
+    debug!("Unpacking archive into {} for verification", tmp_root);
+
+    Archive::new(archive)
+        .unpack(tmp_root)
+        .context("unpacking archive")?;
+
+    let mut cmd = MetadataCommand::new();
+    cmd.manifest_path(tmp_root.join("Cargo.toml"))
+        .other_options(vec!["--locked".to_string(), "--offline".to_string()]);
+    for opt in features.iter() {
+        cmd.features(opt.clone());
+    }
+    let resolved = cmd
+        .exec()
+        .map_err(|err| anyhow!("The skeleton does not resolve offline: {}", err))?;
+
+    let expected: BTreeMap<String, BTreeSet<String>> = lockfile
+        .packages
+        .iter()
+        .map(|pkg| {
+            let deps = pkg
+                .dependencies
+                .iter()
+                .map(|dep| package_key(metadata, dep.as_str()))
+                .collect();
+            (package_key(metadata, pkg.id.as_str()), deps)
+        })
+        .collect();
+
+    let actual: BTreeMap<String, BTreeSet<String>> = resolved
+        .resolve
+        .as_ref()
+        .ok_or_else(|| anyhow!("Metadata missing deps"))?
+        .nodes
+        .iter()
+        .map(|node| {
+            let deps = node
+                .deps
+                .iter()
+                .filter(|dep| {
+                    dep.dep_kinds
+                        .iter()
+                        .any(|kind| kind.kind == DependencyKind::Normal)
+                })
+                .map(|dep| package_key(&resolved, &dep.pkg.repr))
+                .collect();
+            (package_key(&resolved, &node.id.repr), deps)
+        })
+        .collect();
+
+    let mut problems = vec![];
+
+    for (key, deps) in expected.iter() {
+        let resolved_deps = match actual.get(key) {
+            Some(deps) => deps,
+            None => {
+                problems.push(format!("package {} is missing", key));
+                continue;
+            }
+        };
+
+        for dep in deps.difference(resolved_deps) {
+            problems.push(format!("{} no longer depends on {}", key, dep));
+        }
+        for dep in resolved_deps.difference(deps) {
+            problems.push(format!("{} now also depends on {}", key, dep));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "The skeleton resolves differently from the workspace:\n  {}",
+            problems.join("\n  ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns a key for the package that does not depend on where the
+/// workspace is located: `name version (path)` for path packages,
+/// with the path relative to the workspace root, and the package ID
+/// for everything else.
+fn package_key(metadata: &Metadata, id: &str) -> String {
+    let package = match metadata.packages.iter().find(|pkg| pkg.id.repr == id) {
+        Some(package) => package,
+        None => return id.to_string(),
+    };
+
+    if package.source.is_some() {
+        return id.to_string();
+    }
+
+    let dir = package
+        .manifest_path
+        .parent()
+        .unwrap_or(&package.manifest_path);
+    let path = dir.strip_prefix(&metadata.workspace_root).unwrap_or(dir);
+
+    format!("{} {} ({})", package.name, package.version, path)
+}
+
+fn mtime(path: &Utf8Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Returns the newest modification time of a target's sources.
+///
+/// A target in the package root, such as `build.rs`, only has its own
+/// file, since the root also holds other targets and build outputs.
+/// Otherwise every file in the target's directory counts, e.g. the
+/// modules of `src/lib.rs`.
+fn target_source_mtime(
+    package_root: &Utf8Path,
+    src_path: &Utf8Path,
+    skip_dirs: &[&Utf8Path],
+) -> Option<SystemTime> {
+    match src_path.parent() {
+        Some(dir) if dir != package_root => newest_mtime(dir, skip_dirs),
+        _ => mtime(src_path),
+    }
+}
+
+/// Returns the newest modification time of any file within a
+/// directory, recursively. The skipped directories, VCS directories
+/// and nested packages are not walked.
+fn newest_mtime(dir: &Utf8Path, skip_dirs: &[&Utf8Path]) -> Option<SystemTime> {
+    let entries = match dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(_) => return mtime(dir),
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if !path.is_dir() {
+                mtime(path)
+            } else if skip_dirs.contains(&path)
+                || VCS_DIRS.contains(&entry.file_name())
+                || path.join("Cargo.toml").is_file()
+            {
+                None
+            } else {
+                newest_mtime(path, skip_dirs)
+            }
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        process::Command,
+        time::{Duration, SystemTime},
+    };
+
+    fn write(root: &Utf8Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Set the modification time of a file to a minute from now.
+    fn touch_future(path: &Utf8Path) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    /// Build a package with a build script and return its manifest.
+    fn built_package(tmp_dir: &TempDir) -> Utf8PathBuf {
+        let root: &Utf8Path = tmp_dir.path().try_into().unwrap();
+
+        write(
+            root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+        );
+        write(root, "build.rs", "fn main() {}");
+        write(root, "src/main.rs", "mod cli;\nfn main() {}");
+        write(root, "src/cli.rs", "");
+
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--offline", "--quiet"])
+            .current_dir(root)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        root.join("Cargo.toml")
+    }
+
+    #[test]
+    fn fresh_build_passes() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let manifest_path = built_package(&tmp_dir);
+        let root = manifest_path.parent().unwrap();
+
+        // Files written after the build outside of the sources.
+        write(root, ".git/index", "");
+        touch_future(&root.join(".git/index"));
+        write(root, "target/debug/app.log", "");
+        touch_future(&root.join("target/debug/app.log"));
+
+        let checked = verify_sources(VerifySourcesOptions {
+            manifest_path: Some(manifest_path.clone()),
+            check_artifacts: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(2, checked);
+    }
+
+    #[test]
+    fn touched_source_fails() {
+        let tmp_dir = TempDir::with_prefix("cargo-skeleton").unwrap();
+        let manifest_path = built_package(&tmp_dir);
+
+        touch_future(&manifest_path.parent().unwrap().join("src/cli.rs"));
+
+        let err = verify_sources(VerifySourcesOptions {
+            manifest_path: Some(manifest_path),
+            check_artifacts: true,
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            "Artifacts are older than the real sources for packages: app",
+            err.to_string()
+        );
+    }
+}
@@ -1,12 +1,13 @@
-use std::{collections::HashMap, ops::Index};
+use std::{collections::HashMap, env, ops::Index};
 
-use anyhow::{anyhow, Result};
+use anyhow::{Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 use cargo_metadata::Metadata;
 use itertools::Itertools;
 use log::*;
 
 use crate::{
+    error::SkeletonError,
     lockfile::{load_lockfile, Lockfile},
     package::{Package, PackageId},
 };
@@ -35,7 +36,7 @@ impl Workspace {
     }
 
     /// Load the workspace packages from a lockfile.
-    pub fn load_lockfile(&mut self) -> Result<()> {
+    pub fn load_lockfile(&mut self) -> Result<(), SkeletonError> {
         let lockfile = load_lockfile(self.root())?;
 
         for package in lockfile.packages.into_iter() {
@@ -46,11 +47,11 @@ impl Workspace {
     }
 
     /// Load packages from workspace metadata.
-    pub fn load_metadata(&mut self, metadata: &Metadata) -> Result<()> {
-        let packages: Result<Vec<Package>> = metadata
+    pub fn load_metadata(&mut self, metadata: &Metadata) -> Result<(), SkeletonError> {
+        let packages: Result<Vec<Package>, SkeletonError> = metadata
             .packages
             .iter()
-            .filter(|pkg| pkg.source == None)
+            .filter(|pkg| pkg.source.is_none())
             .filter(|pkg| {
                 if !pkg.manifest_path.starts_with(self.root()) {
                     info!("Ignoring local package {} outside of root", pkg.name);
@@ -59,7 +60,7 @@ impl Workspace {
                 true
             })
             .sorted_by(|a, b| Ord::cmp(&a.id, &b.id))
-            .map(|package| -> Result<Package> {
+            .map(|package| -> Result<Package, SkeletonError> {
                 let mut package = Package::from(package);
 
                 package.load_metadata_dependencies(metadata)?;
@@ -84,6 +85,11 @@ impl Workspace {
         self.packages.insert(package.id.clone(), package);
     }
 
+    /// Remove every package that does not match the predicate.
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&Package) -> bool) {
+        self.packages.retain(|_, pkg| f(pkg));
+    }
+
     /// Returns an iterator over all workspace packages.
     pub fn packages(&self) -> impl Iterator<Item = &Package> {
         self.packages.values()
@@ -99,19 +105,24 @@ impl Workspace {
         self.packages.get(id)
     }
 
+    /// Get a mutable reference to a package by ID.
+    pub(crate) fn get_package_mut(&mut self, id: &PackageId) -> Option<&mut Package> {
+        self.packages.get_mut(id)
+    }
+
     /// Find package ids for a slice of specs.
-    pub fn get_package_ids(&self, specs: &[impl AsRef<str>]) -> Result<Vec<&PackageId>> {
+    pub fn get_package_ids(
+        &self,
+        specs: &[impl AsRef<str>],
+    ) -> Result<Vec<&PackageId>, SkeletonError> {
         specs
             .iter()
-            .map(|spec| -> Result<&PackageId> {
-                self.package_id(spec).ok_or_else(|| {
-                    anyhow!(
-                        "package ID specification `{}` did not match any packages",
-                        spec.as_ref()
-                    )
+            .map(|spec| {
+                self.package_id(spec).ok_or_else(|| SkeletonError::PackageNotFound {
+                    spec: spec.as_ref().to_string(),
                 })
             })
-            .collect::<Result<Vec<&PackageId>>>()
+            .collect()
     }
 
     /// Find the id of the package with the given spec.
@@ -126,10 +137,23 @@ impl Workspace {
     pub fn into_lockfile(self) -> Lockfile {
         Lockfile {
             packages: self.packages.into_values().collect(),
+            ..Default::default()
         }
     }
 }
 
+/// Returns the workspace root for the given manifest path, or the
+/// current directory if no manifest path is given.
+pub fn find_root(manifest_path: Option<&Utf8Path>) -> Result<Utf8PathBuf> {
+    if let Some(dir) = manifest_path.and_then(|path| path.parent()) {
+        return Ok(dir.to_owned());
+    }
+
+    let dir = env::current_dir().context("getting current dir")?;
+
+    Utf8PathBuf::try_from(dir).context("current dir should be utf-8")
+}
+
 impl<'a> Index<&'a PackageId> for Workspace {
     type Output = Package;
 
@@ -1,13 +1,12 @@
 use std::{collections::HashMap, fs::File, io::Read};
 
 use camino::Utf8PathBuf;
-use itertools::Itertools;
 use tar::Archive;
-use tempdir::TempDir;
+use tempfile::TempDir;
 
 #[test]
 fn create() {
-    let tmp_dir = TempDir::new("cargo-skeleton").expect("creating temp dir");
+    let tmp_dir = TempDir::with_prefix("cargo-skeleton").expect("creating temp dir");
 
     let out_path: Utf8PathBuf = tmp_dir
         .path()
@@ -18,7 +17,9 @@ fn create() {
     let metadata = cargo_metadata::MetadataCommand::new()
         .exec().expect("running cargo metadata");
 
-    cargo_skeleton::create::create_skeleton(metadata, out_path.clone())
+    let opts = cargo_skeleton::CreateOptions::new(out_path.clone());
+
+    cargo_skeleton::create_skeleton(metadata, opts)
         .expect("creating skeleton");
 
     let file = File::open(&out_path).expect("opening out file");
@@ -64,3 +65,45 @@ fn create() {
     // TODO: assert snapshot maybe?
     assert!(files.contains_key("Skeleton.lock"));
 }
+
+#[test]
+fn create_verified() {
+    let tmp_dir = TempDir::with_prefix("cargo-skeleton").expect("creating temp dir");
+
+    let out_path: Utf8PathBuf = tmp_dir
+        .path()
+        .join("skeleton.tar")
+        .try_into()
+        .expect("converting path to UTF-8");
+
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .exec().expect("running cargo metadata");
+
+    let mut opts = cargo_skeleton::CreateOptions::new(out_path.clone());
+    opts.verify = true;
+
+    cargo_skeleton::create_skeleton(metadata, opts)
+        .expect("creating verified skeleton");
+}
+
+#[test]
+fn create_in_memory() {
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .exec().expect("running cargo metadata");
+
+    let mut archive = vec![];
+
+    let report = cargo_skeleton::SkeletonBuilder::new(metadata)
+        .write(&mut archive)
+        .expect("creating skeleton");
+
+    assert_eq!(archive.len() as u64, report.size);
+    assert_eq!(vec!["cargo-skeleton".to_string()], report.packages);
+
+    let contents = cargo_skeleton::SkeletonArchive::new(&archive[..])
+        .contents()
+        .expect("reading skeleton");
+
+    assert_eq!(report.entries, contents.entries);
+    assert_eq!("cargo-skeleton", contents.lockfile.packages[0].name);
+}